@@ -17,12 +17,35 @@ __email__ = "CaderIdrisGH@outlook.com"
 __status__ = "Stable Release"
 
 import argparse
+import calendar
 import json
+import logging
+import os
+import re
+import sys
+import time
 import datetime as dt
 
 from modules.timetools import TimeCalculator
-from modules.aurn import AURNAPI
+from modules.aurn import (
+        AURNAPI,
+        count_measurements,
+        format_row,
+        metadata_cache_status,
+        validate_patterns,
+        validate_year
+        )
 from modules.influxwrite import InfluxWriter
+from modules.opentsdbwrite import OpenTSDBWriter
+from modules.annotatedcsv import AnnotatedCSVWriter
+from modules.lineprotocol import (LineProtocolFileWriter,
+                                  LineProtocolStreamWriter)
+from modules.manifest import ExportManifest
+from modules.parquetwrite import ParquetWriter
+from modules.aggregate import SiteStatistics, aggregate_container_list
+from modules.ratelimit import RateLimiter
+from modules.metrics import MetricsServer
+from modules.transform import apply_transform, load_transform
 
 
 def parse_date_string(dateString):
@@ -65,6 +88,99 @@ def parse_date_string(dateString):
     )
 
 
+def parse_end_date_string(dateString):
+    """Parses input strings in to the end of the period they describe
+
+    An end date of 2020 should include all of 2020, not stop at midnight
+    on the 1st of January. The end of the year, month or day given is
+    returned depending on how much of the date was provided
+
+    Keyword arguments:
+        date_string (str): String to be parsed in to date object
+
+    Variables:
+        date_parts (int): Number of numbers in the string, 1 for year
+        only, 2 for year and month, 3 for a full date
+
+    Returns:
+        Datetime object at the start of the next year, month or day,
+        measurements after this time are outside the range. AURN
+        timestamps mark the end of the hour measured so midnight at the
+        end of the range is included
+
+    Raises:
+        ValueError if input isn't in a suitable format
+
+    """
+    end_date = parse_date_string(dateString)
+    date_parts = len(re.findall(r"\d+", dateString))
+    if date_parts == 1:
+        return end_date.replace(year=end_date.year + 1)
+    if date_parts == 2:
+        if end_date.month == 12:
+            return end_date.replace(year=end_date.year + 1, month=1)
+        return end_date.replace(month=end_date.month + 1)
+    return end_date + dt.timedelta(days=1)
+
+
+def parse_relative_date(expression, now):
+    """Parses relative date expressions in to date objects
+
+    Recurring jobs can use expressions relative to the current time
+    instead of fixed dates. "now" is the current time, otherwise the
+    expression is a number followed by a unit and that amount of time is
+    subtracted from now. Units are:
+        d: days
+        w: weeks
+        m: months (calendar months, day clamped to end of month)
+        y: years
+
+    Keyword arguments:
+        expression (str): Expression to parse e.g 30d, 1y or now
+
+        now (datetime): Time the expression is relative to
+
+    Variables:
+        amount (int): Number of units to go back
+
+        unit (str): Unit of amount
+
+    Returns:
+        Datetime object equivalent of input
+
+    Raises:
+        ValueError if input isn't a valid expression
+
+    """
+    expression = expression.strip().lower()
+    if expression == "now":
+        return now
+    relative_match = re.fullmatch(r"(\d+)([dwmy])", expression)
+    if relative_match is None:
+        raise ValueError(
+            f'"{expression}" is not a valid relative date. Please use '
+            f"now or a number followed by d (days), w (weeks), "
+            f"m (months) or y (years) e.g 30d"
+        )
+    amount = int(relative_match.group(1))
+    unit = relative_match.group(2)
+    if unit == "d":
+        return now - dt.timedelta(days=amount)
+    if unit == "w":
+        return now - dt.timedelta(weeks=amount)
+    if unit == "m":
+        total_months = (now.year * 12 + now.month - 1) - amount
+        year, month = divmod(total_months, 12)
+        month += 1
+    else:
+        year, month = now.year - amount, now.month
+    return now.replace(
+        year=year,
+        month=month,
+        day=min(now.day, calendar.monthrange(year, month)[1])
+    )
+
+
 def fancy_print(
     str_to_print,
     length=70,
@@ -151,19 +267,20 @@ def get_json(pathToJson):
         Raises:
             FileNotFoundError if file is not present, blank file created
 
-            ValueError if file can not be parsed
+            ValueError if file can not be parsed, including the line and
+            column of the error
     """
 
     try:
         with open(pathToJson, "r") as jsonFile:
             try:
                 return json.load(jsonFile)
-            except json.decoder.JSONDecodeError:
+            except json.decoder.JSONDecodeError as json_error:
                 raise ValueError(
-                    f"{pathToJson} is not in the proper"
-                    f"format. If you're having issues, consider"
-                    f"using the template from the Github repo or "
-                    f" use the format seen in README.md"
+                    f"{pathToJson} is not in the proper "
+                    f"format ({json_error}). If you're having issues, "
+                    f"consider using the template from the Github repo or "
+                    f"use the format seen in README.md"
                 )
     except FileNotFoundError:
         raise FileNotFoundError(
@@ -173,6 +290,113 @@ def get_json(pathToJson):
         )
 
 
+def get_site_list(pathToList):
+    """Reads a list of site codes from a text or csv file
+
+    Codes can be one per line, comma separated or both. Blank entries and
+    lines starting with # are ignored
+
+        Keyword Arguments:
+            pathToList (str): Path to the site list
+
+        Returns:
+            List of site codes in the order they appear in the file
+
+        Raises:
+            FileNotFoundError if file is not present
+    """
+    site_codes = list()
+    try:
+        with open(pathToList, "r") as listFile:
+            for line in listFile:
+                if line.strip().startswith("#"):
+                    continue
+                for code in line.split(","):
+                    code = code.strip().strip('"')
+                    if code != "" and code not in site_codes:
+                        site_codes.append(code)
+    except FileNotFoundError:
+        raise FileNotFoundError(
+            f"{pathToList} could not be found, check the path given "
+            f"with --site-list"
+        )
+    return site_codes
+
+
+def limit_container_list(list_of_containers, points_written, max_points):
+    """ Cuts a list of containers to the number that can still be written
+    before the --max-points limit
+
+        Keyword Arguments:
+            list_of_containers (list): Containers to be written
+
+            points_written (int): Containers written so far
+
+            max_points (int): Maximum containers to write, 0 for no limit
+
+        Returns:
+            Tuple of the containers that can be written and whether any
+            were cut
+    """
+    if max_points <= 0 or \
+            points_written + len(list_of_containers) <= max_points:
+        return list_of_containers, False
+    return list_of_containers[:max(max_points - points_written, 0)], True
+
+
+def find_count_mismatch(aurn, site_name, download_code, year, start_date,
+                        end_date):
+    """ Checks no measurements were lost converting a csv to containers
+
+    The measurements counted in the downloaded csv are compared with those
+    in the converted containers, so a measurement dropped by mistake is
+    reported in the summary instead of going unnoticed
+
+        Keyword Arguments:
+            aurn (AURNAPI): Has the csv and its containers for the year
+
+            site_name (str): Name of the site, used in the summary
+
+            download_code (str): Download code of the site
+
+            year (int): Year the measurements were made
+
+            start_date (datetime): Start of the export
+
+            end_date (datetime): End of the export
+
+        Returns:
+            Tuple of site name, year, downloaded count and exported count
+            if the counts differ, None if they agree
+    """
+    downloaded_count = aurn.count_valid_measurements(
+            download_code, year, start_date, end_date
+            )
+    converted_count = count_measurements(
+            aurn.measurement_jsons[year][download_code]
+            )
+    if downloaded_count == converted_count:
+        return None
+    return site_name, year, downloaded_count, converted_count
+
+
+def print_count_mismatches(count_mismatches):
+    """ Prints the sites and years where measurements were lost
+
+        Keyword Arguments:
+            count_mismatches (list): Tuples from find_count_mismatch.
+            Nothing is printed if empty
+    """
+    if len(count_mismatches) == 0:
+        return
+    fancy_print("Measurement count mismatches (downloaded/exported):")
+    for site_name, year, downloaded_count, converted_count in \
+            count_mismatches:
+        fancy_print(f"{site_name} ({year}): {downloaded_count}/"
+                    f"{converted_count}")
+    fancy_print("", form="LINE")
+
+
 if __name__ == "__main__":
     # Parse incoming arguments
     arg_parser = argparse.ArgumentParser(
@@ -199,12 +423,207 @@ if __name__ == "__main__":
         type=str,
         help="Alternate location for config json file (Defaults to "
         "./Settings/config.json)",
-        default="Settings/config.json",
+        default=None,
+    )
+    arg_parser.add_argument(
+        "--no-default-config",
+        action="store_true",
+        help="Exit with an error if no config file is given with -c "
+        "instead of using ./Settings/config.json",
+    )
+    arg_parser.add_argument(
+        "-o",
+        "--output-format",
+        type=str,
+        choices=["InfluxDB", "OpenTSDB", "Annotated CSV",
+                 "Line Protocol File", "Parquet"],
+        help="Where to export measurements to, overrides Export Backend "
+        "in the config",
+        default=None,
+    )
+    arg_parser.add_argument(
+        "--output-dir",
+        type=str,
+        help="Directory to save annotated csvs, line protocol and Parquet "
+        "files to, overrides the directories in the config",
+        default="",
+    )
+    arg_parser.add_argument(
+        "--influx-url",
+        type=str,
+        help="Url of the InfluxDB database e.g http://localhost:8086, "
+        "overrides Influx IP and Influx Port in the config",
+        default="",
+    )
+    arg_parser.add_argument(
+        "--dry-run",
+        action="store_true",
+        help="Print the line protocol that would be written instead of "
+        "exporting it. The manifest isn't saved",
+    )
+    arg_parser.add_argument(
+        "-r",
+        "--require-data",
+        action="store_true",
+        help="Exit with an error if no measurements were written",
+    )
+    arg_parser.add_argument(
+        "-l",
+        "--list-urls",
+        action="store_true",
+        help="Print the url of every measurement csv that would be "
+        "downloaded, one per line, then exit without downloading",
+    )
+    arg_parser.add_argument(
+        "--cache-status",
+        action="store_true",
+        help="Print the age of the metadata cache and whether it is older "
+        "than Metadata Cache TTL, then exit without downloading",
+    )
+    arg_parser.add_argument(
+        "--validate-config",
+        action="store_true",
+        help="Check every XPath in the config, print all that are invalid, "
+        "then exit without downloading",
+    )
+    arg_parser.add_argument(
+        "--verify-codes",
+        action="store_true",
+        help="Test fetch the end year's measurement csv for every site, "
+        "print the sites whose csv couldn't be downloaded, then exit "
+        "without exporting",
+    )
+    arg_parser.add_argument(
+        "-m",
+        "--resume-manifest",
+        type=str,
+        help="Manifest from a previous run, sites, pollutants and years "
+        "in it are not exported again",
+        default="",
+    )
+    arg_parser.add_argument(
+        "--ratified-only",
+        action="store_true",
+        help="Only export pollutants recorded as provisional in the "
+        "manifest given with --resume-manifest that have since been "
+        "ratified",
+    )
+    arg_parser.add_argument(
+        "--changed-only",
+        action="store_true",
+        help="Only export days whose measurements have changed since the "
+        "export recorded in the manifest given with --resume-manifest. "
+        "The manifest must have been saved with Manifest Checksums on",
+    )
+    arg_parser.add_argument(
+        "--load-metadata",
+        type=str,
+        help="Json file of site metadata saved with --save-metadata, used "
+        "instead of downloading metadata from DEFRA",
+        default="",
+    )
+    arg_parser.add_argument(
+        "--save-metadata",
+        type=str,
+        help="Save the site metadata to a json file, after any site "
+        "filters are applied",
+        default="",
+    )
+    arg_parser.add_argument(
+        "-S",
+        "--site-list",
+        type=str,
+        help="Text or csv file of site codes (download code or UK-AIR ID) "
+        "to export, all sites are exported if not given",
+        default="",
+    )
+    arg_parser.add_argument(
+        "-p",
+        "--pollutant",
+        type=str,
+        help="Only export sites whose metadata lists this pollutant e.g "
+        "NO2, case insensitive",
+        default="",
+    )
+    arg_parser.add_argument(
+        "--region",
+        type=str,
+        action="append",
+        help="Only export sites in this region or country e.g Scotland, "
+        "case insensitive. Can be given more than once",
+        default=None,
+    )
+    arg_parser.add_argument(
+        "--environment",
+        type=str,
+        action="append",
+        help="Only export sites with this environment type e.g Urban "
+        "Background, or any type starting with it e.g Urban. Case "
+        "insensitive, can be given more than once",
+        default=None,
+    )
+    arg_parser.add_argument(
+        "-n",
+        "--max-points",
+        type=int,
+        help="Stop once this many measurements have been written, useful "
+        "for test runs (Defaults to no limit)",
+        default=0,
+    )
+    arg_parser.add_argument(
+        "--since",
+        type=str,
+        help="Relative start of data export e.g 30d, 2w, 6m, 1y. Used "
+        "instead of --start-date",
+        default="",
+    )
+    arg_parser.add_argument(
+        "--until",
+        type=str,
+        help="Relative end of data export e.g now, 1d. Used instead of "
+        "--end-date, defaults to now if --since is given",
+        default="",
     )
     args = vars(arg_parser.parse_args())
+    run_start = time.monotonic()
     start_date_string = args["start_date"]
     end_date_string = args["end_date"]
     config_path = args["config"]
+    if config_path is None:
+        if args["no_default_config"]:
+            raise ValueError(
+                "No config file given and --no-default-config was set, "
+                "please provide a config file with -c"
+            )
+        config_path = "Settings/config.json"
+    require_data = args["require_data"]
+    list_urls = args["list_urls"]
+    verify_codes = args["verify_codes"]
+    validate_config = args["validate_config"]
+    cache_status = args["cache_status"]
+    resume_manifest_path = args["resume_manifest"]
+    ratified_only = args["ratified_only"]
+    changed_only = args["changed_only"]
+    site_list_path = args["site_list"]
+    load_metadata_path = args["load_metadata"]
+    save_metadata_path = args["save_metadata"]
+    pollutant_filter = args["pollutant"]
+    region_filter = args["region"]
+    environment_filter = args["environment"]
+    max_points = args["max_points"]
+    since_string = args["since"]
+    until_string = args["until"]
+    output_format = args["output_format"]
+    output_dir = args["output_dir"]
+    influx_url = args["influx_url"]
+    dry_run = args["dry_run"]
+
+    # Line protocol from --dry-run and urls from --list-urls are written
+    # to stdout so they can be piped or redirected to a file, everything
+    # else is printed to stderr
+    output_stream = sys.stdout
+    if dry_run or list_urls:
+        sys.stdout = sys.stderr
 
     # Blurb
     fancy_print("", form="LINE")
@@ -216,14 +635,67 @@ if __name__ == "__main__":
     fancy_print(f"License: {__license__}")
     fancy_print("", form="LINE")
 
-    # Get dates
-    if "N/A" in [start_date_string, end_date_string]:
+    # Report on the metadata cache without needing dates, then exit
+    if cache_status:
+        config_settings = get_json(config_path)
+        cache_path = config_settings.get("Metadata Cache", "")
+        if cache_path == "":
+            fancy_print(f"No Metadata Cache set in {config_path}")
+            fancy_print("", form="LINE")
+            raise SystemExit(0)
+        status = metadata_cache_status(
+                cache_path, config_settings.get("Metadata Cache TTL", 7)
+                )
+        fancy_print(f"Metadata cache: {status['path']}")
+        if status["saved"] is None:
+            fancy_print("Saved: Missing")
+        else:
+            fancy_print(
+                    f"Saved: {status['saved'].strftime('%Y-%m-%d %H:%M:%S')}"
+                    f" UTC"
+                    )
+            fancy_print(f"Age: {status['age']:.1f} days")
+        fancy_print(f"Stale: {'Yes' if status['stale'] else 'No'}")
+        fancy_print("", form="LINE")
+        raise SystemExit(1 if status["stale"] else 0)
+
+    if changed_only and resume_manifest_path == "":
+        raise ValueError(
+            "--changed-only needs the manifest of a previous run, "
+            "please provide one with --resume-manifest"
+        )
+    if ratified_only and resume_manifest_path == "":
+        raise ValueError(
+            "--ratified-only needs the manifest of a previous run, "
+            "please provide one with --resume-manifest"
+        )
+
+    # Get dates, relative expressions are used over fixed dates if given
+    if since_string != "" and until_string == "":
+        until_string = "now"
+    if ((start_date_string == "N/A" and since_string == "") or
+            (end_date_string == "N/A" and until_string == "")):
         raise ValueError(
             "One or more required years not provided, "
             "please provide start (-s) and end (-e) year as arguments"
         )
-    start_date = parse_date_string(start_date_string)
-    end_date = parse_date_string(end_date_string)
+    # AURN timestamps are GMT
+    now = dt.datetime.now(dt.timezone.utc).replace(tzinfo=None)
+    if since_string != "":
+        start_date = parse_relative_date(since_string, now)
+    else:
+        start_date = parse_date_string(start_date_string)
+    if until_string != "":
+        end_date = parse_relative_date(until_string, now)
+        end_bound = end_date
+    else:
+        end_date = parse_date_string(end_date_string)
+        end_bound = parse_end_date_string(end_date_string)
+    if end_date < start_date:
+        raise ValueError(
+            f"End date {end_date.strftime('%Y-%m-%d')} is before start date "
+            f"{start_date.strftime('%Y-%m-%d')}"
+        )
     time_config = TimeCalculator(start_date, end_date)
     number_of_years = time_config.year_difference()
 
@@ -235,8 +707,43 @@ if __name__ == "__main__":
     # Read config file
     config_settings = get_json(config_path)
     fancy_print(f"Imported settings from {config_path}")
+    # Log which sites and urls were processed, for diagnosing long runs
+    logging.basicConfig(
+            level=config_settings.get("Log Level", "WARNING"),
+            format="%(asctime)s %(levelname)s %(name)s: %(message)s"
+            )
+    # Command line options take priority over the config
+    if output_format is not None:
+        config_settings["Export Backend"] = output_format
+        fancy_print(f"Exporting to {output_format}")
+    if output_dir != "":
+        config_settings["Annotated CSV Directory"] = output_dir
+        config_settings["Line Protocol File"] = os.path.join(
+                output_dir,
+                os.path.basename(
+                    config_settings.get("Line Protocol File", "")
+                    ) or "aurn.lp.gz"
+                )
+        config_settings["Parquet File"] = os.path.join(
+                output_dir,
+                os.path.basename(
+                    config_settings.get("Parquet File", "")
+                    ) or "aurn.parquet"
+                )
+        fancy_print(f"Saving files to {output_dir}")
+    if influx_url != "":
+        config_settings["Influx URL"] = influx_url
     fancy_print("", form="LINE")
 
+    # Report every invalid XPath at once and exit if requested
+    if validate_config:
+        pattern_errors = validate_patterns(config_settings)
+        for key, error in pattern_errors:
+            fancy_print(f"{key}: {error}")
+        fancy_print(f"{len(pattern_errors)} invalid XPaths in {config_path}")
+        fancy_print("", form="LINE")
+        raise SystemExit(1 if len(pattern_errors) > 0 else 0)
+
     # Debug stats
     if config_settings["Debug Stats"]:
         fancy_print("DEBUG STATS", form="TITLE")
@@ -249,13 +756,110 @@ if __name__ == "__main__":
         fancy_print("")
         fancy_print("", form="LINE")
 
-    # Connect to InfluxDB 2.0 Database
-    influx = InfluxWriter(config_settings)
+    # Connect to export database, InfluxDB 2.0 unless another specified
+    if dry_run:
+        influx = LineProtocolStreamWriter(output_stream)
+    elif config_settings.get("Export Backend", "InfluxDB") == "OpenTSDB":
+        influx = OpenTSDBWriter(config_settings)
+    elif config_settings.get("Export Backend") == "Annotated CSV":
+        influx = AnnotatedCSVWriter(config_settings)
+    elif config_settings.get("Export Backend") == "Line Protocol File":
+        influx = LineProtocolFileWriter(config_settings)
+    elif config_settings.get("Export Backend") == "Parquet":
+        influx = ParquetWriter(config_settings)
+    else:
+        influx = InfluxWriter(config_settings)
+        if not influx.is_available():
+            if config_settings.get("On Sink Unavailable", "Fail") == \
+                    "Spool To File":
+                fancy_print(f"InfluxDB unavailable, spooling measurements to "
+                            f"{config_settings['Line Protocol File']}")
+                fancy_print("", form="LINE")
+                influx = LineProtocolFileWriter(config_settings)
+            else:
+                raise ConnectionError(
+                    f"InfluxDB at {influx.url} could not be reached. "
+                    f"Set On Sink Unavailable to Spool To File in config to "
+                    f"save measurements to a line protocol file instead"
+                )
 
     # Get metadata from AURN
     fancy_print("Downloading metadata from DEFRA...", end="\r", flush=True)
-    aurn = AURNAPI(config_settings)
-    aurn.get_metadata(start_date.year, end_date.year)
+    rate_limiter = RateLimiter(config_settings.get("Requests Per Second", 0))
+    aurn = AURNAPI(config_settings, rate_limiter)
+    # Catch typos in years before anything is downloaded
+    for year in [start_date.year, end_date.year]:
+        validate_year(year, aurn.min_year, aurn.max_year)
+
+    # The run is stopped cleanly once Max Runtime seconds have passed,
+    # whether it is scraping metadata or exporting measurements
+    stop_reason = ""
+    max_runtime = config_settings.get("Max Runtime", 0)
+    run_deadline = run_start + max_runtime if max_runtime > 0 else None
+
+    # Serve progress as Prometheus metrics if an address is given
+    run_phase = "metadata"
+    points_written = 0
+    if config_settings.get("Metrics Address", "") != "":
+        metrics_server = MetricsServer(
+                config_settings["Metrics Address"],
+                config_settings.get("Metrics Port", 9100),
+                lambda: {
+                    "phase": run_phase,
+                    "requests_total": aurn.request_count,
+                    "request_failures_total": aurn.failed_requests,
+                    "points_written_total": points_written
+                    }
+                )
+        metrics_server.start()
+    # Show how many site info pages have been scraped, as it can take
+    # minutes with no other output
+    scrape_progress = None
+    if config_settings.get("Show Progress", True):
+        def scrape_progress(scraped, total):
+            fancy_print(f"Scraping site {scraped} of {total}", end="\r",
+                        flush=True)
+    if load_metadata_path != "":
+        aurn.load_metadata(load_metadata_path)
+        fancy_print(f"Loaded metadata from {load_metadata_path}")
+    elif config_settings.get("Metadata Cache", "") != "":
+        if aurn.get_metadata_cached(
+                start_date.year,
+                end_date.year,
+                config_settings["Metadata Cache"],
+                config_settings.get("Metadata Cache TTL", 7),
+                scrape_progress,
+                run_deadline):
+            fancy_print(f"Loaded metadata from "
+                        f"{config_settings['Metadata Cache']}")
+    else:
+        inactive_sites = aurn.get_metadata(start_date.year, end_date.year,
+                                           scrape_progress, run_deadline)
+        fancy_print(f"{inactive_sites} stations not active within date "
+                    f"range")
+    if not aurn.metadata_complete:
+        stop_reason = (f"Exceeded max runtime of {max_runtime}s while "
+                       f"downloading metadata")
+        fancy_print(stop_reason)
+    if site_list_path != "":
+        missing_sites = aurn.select_sites(get_site_list(site_list_path))
+        for missing_site in missing_sites:
+            fancy_print(f"{missing_site} in {site_list_path} not found")
+    if pollutant_filter != "":
+        removed_sites = aurn.filter_by_pollutant(pollutant_filter)
+        fancy_print(f"{removed_sites} stations don't measure "
+                    f"{pollutant_filter}")
+    if region_filter is not None:
+        removed_sites = aurn.filter_by_region(region_filter)
+        fancy_print(f"{removed_sites} stations aren't in "
+                    f"{', '.join(region_filter)}")
+    if environment_filter is not None:
+        removed_sites = aurn.filter_by_environment(environment_filter)
+        fancy_print(f"{removed_sites} stations aren't of type "
+                    f"{', '.join(environment_filter)}")
+    if save_metadata_path != "":
+        aurn.save_metadata(save_metadata_path)
+        fancy_print(f"Saved metadata to {save_metadata_path}")
     fancy_print(f"{len(aurn.metadata)} stations measuring within date range")
     if config_settings["Debug Stats"]:
         for station in aurn.metadata:
@@ -263,28 +867,309 @@ if __name__ == "__main__":
                     f"{station['tags']['Site Name']}: "
                     f"{station['tags']['Download Code']}"
                     )
+        fancy_print("", form="LINE")
+        fancy_print("SITES BY POLLUTANT COUNT", form="TITLE")
+        for site_name, download_code, pollutant_count in \
+                aurn.sites_by_pollutant_count():
+            fancy_print(f"{site_name} ({download_code}): {pollutant_count}")
+        fancy_print("", form="LINE")
+        fancy_print("DROPPED SITES", form="TITLE")
+        for reason, row in aurn.dropped_sites:
+            fancy_print("")
+            fancy_print(reason)
+            for row_line in format_row(row).split("\n"):
+                fancy_print(row_line[:66])
     fancy_print("", form="LINE")
 
+    # Print measurement csv urls and exit if requested
+    if list_urls:
+        for station in aurn.metadata:
+            for year_offset in range(0, number_of_years + 1):
+                print(aurn.measurement_csv_url(
+                    station['tags']['Download Code'],
+                    start_date.year + year_offset
+                    ), file=output_stream)
+        raise SystemExit(0)
+
+    # Check every download code can be used before a full export
+    if verify_codes:
+        failed_sites = aurn.verify_site_codes(end_date.year)
+        for site_name, download_code, error in failed_sites:
+            fancy_print(f"{site_name} ({download_code}): {error}")
+        fancy_print(f"{len(failed_sites)} of {len(aurn.metadata)} site "
+                    f"codes failed for {end_date.year}")
+        fancy_print("", form="LINE")
+        raise SystemExit(1 if len(failed_sites) > 0 else 0)
+
+    # Record of everything exported, starting from the previous run's
+    # manifest if resuming
+    previous_manifest = ExportManifest()
+    manifest = ExportManifest()
+    if resume_manifest_path != "":
+        previous_manifest = ExportManifest.load(resume_manifest_path)
+        manifest = ExportManifest.load(resume_manifest_path)
+        fancy_print(f"Resuming from {len(previous_manifest.entries)} "
+                    f"manifest entries")
+        fancy_print("", form="LINE")
+    manifest.record_checksums = (
+            config_settings.get("Manifest Checksums", False) or changed_only
+            )
+    # Custom corrections applied to measurements before export
+    transform = None
+    if config_settings.get("Transform", "") != "":
+        transform = load_transform(config_settings["Transform"])
+    site_statistics = list()
+    skipped_combinations = 0
+    count_mismatches = list()
+    ratified_combinations = 0
+    unchanged_points = 0
+    run_phase = "measurements"
+
+    # Measurement csvs are downloaded ahead of the one being exported if
+    # "Download Workers" is more than 1, in the order they're exported
+    site_years = [
+            (station['tags']['Download Code'], start_date.year + year_offset)
+            for station in aurn.metadata
+            for year_offset in range(0, number_of_years + 1)
+            if not (ratified_only and previous_manifest.is_ratified(
+                station['tags']['Download Code'],
+                start_date.year + year_offset
+                ))
+            ]
+    csv_downloads = aurn.iter_csv_measurements(
+            site_years, config_settings.get("Download Workers", 1)
+            )
+
     # Loop over station, then years
     for station in aurn.metadata:
+        if stop_reason != "":
+            break
+        station_statistics = SiteStatistics(station)
         for year_offset in range(0, number_of_years + 1):
+            if (run_deadline is not None and
+                    time.monotonic() > run_deadline):
+                stop_reason = f"Exceeded max runtime of {max_runtime}s"
+                break
             # Download csv measurements
             year = start_date.year + year_offset
             download_code = station['tags']['Download Code']
+            if (ratified_only and
+                    previous_manifest.is_ratified(download_code, year)):
+                continue  # Nothing left to ratify, don't download
             fancy_print(f"Downloading data for {station['tags']['Site Name']}"
                         f" ({year})", end="\r", flush=True)
-            aurn.get_csv_measurements(download_code, year)
+            next(csv_downloads)
             if aurn.measurement_csvs[year][download_code] is None:
-                aurn.clear_measurement_csvs()
+                aurn.clear_measurement_csvs(download_code, year)
                 continue  # If the csv couldn't be found, skip
             # Reformat csv to json list
             fancy_print(f"Exporting data for {station['tags']['Site Name']}"
                         f" ({year})", end="\r", flush=True)
-            aurn.csv_to_json_list(station, download_code, year)
+            aurn.csv_to_json_list(station, download_code, year,
+                                  start_date, end_bound)
+            # Check no measurements were lost converting the csv
+            count_mismatch = find_count_mismatch(
+                    aurn, station['tags']['Site Name'], download_code, year,
+                    start_date, end_bound
+                    )
+            if count_mismatch is not None:
+                count_mismatches.append(count_mismatch)
+            aurn.remove_boundary_duplicates(download_code, year)
+            if changed_only:
+                manifest.replace_container_list(
+                        download_code,
+                        year,
+                        aurn.measurement_jsons[year][download_code]
+                        )
+                container_list, unchanged_count = \
+                    previous_manifest.filter_changed(
+                        download_code,
+                        year,
+                        aurn.measurement_jsons[year][download_code]
+                        )
+                aurn.measurement_jsons[year][download_code] = container_list
+                unchanged_points += unchanged_count
+            elif ratified_only:
+                container_list, ratified_pollutants = \
+                    previous_manifest.filter_newly_ratified(
+                        download_code,
+                        year,
+                        aurn.measurement_jsons[year][download_code]
+                        )
+                aurn.measurement_jsons[year][download_code] = container_list
+                ratified_combinations += len(ratified_pollutants)
+                for pollutant in ratified_pollutants:
+                    manifest.remove(download_code, pollutant, year)
+            elif len(previous_manifest.entries) > 0:
+                container_list, skipped_pollutants = \
+                    previous_manifest.filter_container_list(
+                        download_code,
+                        year,
+                        aurn.measurement_jsons[year][download_code]
+                        )
+                aurn.measurement_jsons[year][download_code] = container_list
+                skipped_combinations += len(skipped_pollutants)
+            if transform is not None:
+                aurn.measurement_jsons[year][download_code] = \
+                    apply_transform(
+                        aurn.measurement_jsons[year][download_code],
+                        transform
+                        )
+            aurn.measurement_jsons[year][download_code], truncated = \
+                limit_container_list(
+                    aurn.measurement_jsons[year][download_code],
+                    points_written, max_points
+                    )
+            if truncated:
+                stop_reason = f"Reached limit of {max_points} measurements"
             influx.write_container_list(
                     aurn.measurement_jsons[year][download_code]
                     )
-            aurn.clear_measurement_csvs()
+            points_written += len(
+                    aurn.measurement_jsons[year][download_code]
+                    )
+            if truncated:
+                # Only some of the site and year was written, leave it out
+                # of the manifest so --resume-manifest exports it again
+                manifest.remove_site_year(download_code, year)
+            elif not changed_only:
+                manifest.add_container_list(
+                        download_code,
+                        year,
+                        aurn.measurement_jsons[year][download_code]
+                        )
+            station_statistics.add_container_list(
+                    aurn.measurement_jsons[year][download_code]
+                    )
+            for aggregate_period in ["daily", "monthly"]:
+                if not config_settings.get(
+                        f"{aggregate_period.capitalize()} Aggregates", False
+                        ):
+                    continue
+                aggregates, truncated = limit_container_list(
+                        aggregate_container_list(
+                            aurn.measurement_jsons[year][download_code],
+                            period=aggregate_period,
+                            min_capture=config_settings.get(
+                                "Aggregate Min Capture", 0.75
+                                ),
+                            measurement=f"air_quality_{aggregate_period}",
+                            method_tag=config_settings.get(
+                                "Method Tag", "method"
+                                )
+                            ),
+                        points_written, max_points
+                        )
+                if truncated:
+                    stop_reason = (f"Reached limit of {max_points} "
+                                   f"measurements")
+                influx.write_container_list(aggregates)
+                points_written += len(aggregates)
+            aurn.clear_measurement_csvs(download_code, year)
             aurn.clear_measurement_jsons()
+            if stop_reason != "":
+                break
+        if len(station_statistics.pollutants) > 0:
+            site_statistics.append(station_statistics.as_dict())
+            if config_settings.get("Site Statistics", False):
+                statistics, truncated = limit_container_list(
+                        [station_statistics.as_container()],
+                        points_written, max_points
+                        )
+                if truncated:
+                    stop_reason = (f"Reached limit of {max_points} "
+                                   f"measurements")
+                influx.write_container_list(statistics)
+                points_written += len(statistics)
+        # Measurements can be held back to fill a batch, write them before
+        # moving on so a stopped export doesn't lose them
+        if hasattr(influx, "flush"):
+            influx.flush()
+        # Save progress so an interrupted export can be resumed with
+        # --resume-manifest without downloading finished sites again
+        if (not dry_run and config_settings.get("Manifest Path", "") != ""
+                and config_settings.get("Manifest Checkpoints", True)):
+            manifest.save(config_settings["Manifest Path"])
         fancy_print(f"{station['tags']['Site Name']} Finished")
+    # Wait for csvs downloaded ahead if the export stopped early
+    csv_downloads.close()
+    # Parquet files can't be read until their footer is written
+    if hasattr(influx, "close"):
+        influx.close()
     fancy_print("", form="LINE")
+    run_phase = "finished"
+    if stop_reason != "":
+        fancy_print(f"Export stopped early: {stop_reason}")
+    fancy_print(f"Wrote {points_written} measurements")
+    if aurn.out_of_range_points > 0:
+        if config_settings.get("Drop Out Of Range Points", True):
+            fancy_print(f"Dropped {aurn.out_of_range_points} measurements "
+                        f"outside date range")
+        else:
+            fancy_print(f"Kept {aurn.out_of_range_points} measurements "
+                        f"outside date range")
+    if changed_only:
+        fancy_print(f"Skipped {unchanged_points} measurements unchanged "
+                    f"since the last export")
+    elif ratified_only:
+        fancy_print(f"Exported {ratified_combinations} newly ratified site, "
+                    f"pollutant and year combinations")
+    elif resume_manifest_path != "":
+        fancy_print(f"Skipped {skipped_combinations} site, pollutant and "
+                    f"year combinations already in manifest")
+    if hasattr(influx, "retry_policy"):
+        fancy_print(f"Retried {influx.retry_policy.retry_count} writes")
+    if hasattr(influx, "write_count"):
+        fancy_print(f"Sent {influx.write_count} write requests")
+    fancy_print("", form="LINE")
+    if aurn.rate_limited_count > 0:
+        fancy_print(f"Rate limited or challenged by DEFRA "
+                    f"{aurn.rate_limited_count} times")
+        fancy_print("", form="LINE")
+    if aurn.retry_policy.retry_count > 0:
+        fancy_print(f"Retried {aurn.retry_policy.retry_count} requests to "
+                    f"DEFRA after transient errors")
+        fancy_print("", form="LINE")
+    if len(aurn.request_errors) > 0:
+        error_counts = dict()
+        for request_error in aurn.request_errors:
+            status = request_error.status_code
+            if status is None:
+                status = "No response"
+            error_counts[status] = error_counts.get(status, 0) + 1
+        fancy_print("Failed requests to DEFRA:")
+        for status, error_count in sorted(error_counts.items(), key=str):
+            fancy_print(f"{status}: {error_count}")
+        fancy_print("", form="LINE")
+    if aurn.boundary_duplicates > 0:
+        fancy_print(f"Removed {aurn.boundary_duplicates} measurements "
+                    f"duplicated between consecutive years")
+        fancy_print("", form="LINE")
+    print_count_mismatches(count_mismatches)
+    if len(config_settings.get("Zero As Missing", [])) > 0:
+        fancy_print(f"Dropped {aurn.dropped_zeros} zero measurements")
+        fancy_print("", form="LINE")
+    if len(config_settings.get("Allowed Statuses", [])) > 0:
+        fancy_print(f"Dropped {aurn.dropped_statuses} measurements without "
+                    f"an allowed status")
+        fancy_print("", form="LINE")
+    if config_settings.get("Site Statistics Path", "") != "":
+        with open(config_settings["Site Statistics Path"], "w") as stats_file:
+            json.dump(site_statistics, stats_file, indent=2)
+        fancy_print(f"Saved site statistics to "
+                    f"{config_settings['Site Statistics Path']}")
+        fancy_print("", form="LINE")
+    if dry_run:
+        fancy_print(f"Dry run, printed {influx.line_count} lines of line "
+                    f"protocol")
+        fancy_print("", form="LINE")
+    elif config_settings.get("Manifest Path", "") != "":
+        manifest.save(config_settings["Manifest Path"])
+        fancy_print(f"Saved manifest to {config_settings['Manifest Path']}")
+        fancy_print("", form="LINE")
+    if require_data and points_written == 0:
+        raise RuntimeError(
+            "No measurements were written and --require-data was set. "
+            "Check the AURN Data Link in config and that the date range "
+            "contains measurements"
+        )
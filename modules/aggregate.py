@@ -0,0 +1,245 @@
+""" Contains functions that aggregate hourly measurements to daily or
+monthly means and maxima
+
+The AURN measures most pollutants hourly. Some dashboards only need daily
+or monthly values, so the hourly containers for a site can be aggregated
+before export. Following the usual air quality data capture rules, a
+period's mean is only valid if enough of its hours have measurements
+
+    Classes:
+        SiteStatistics: Minimum, maximum, mean and count of each pollutant
+        measured at a site over the whole export
+
+    Functions:
+        aggregate_container_list: Aggregates hourly containers to daily or
+        monthly containers
+
+"""
+
+__author__ = "Idris Hayward"
+__copyright__ = "2021, Idris Hayward"
+__credits__ = ["Idris Hayward"]
+__license__ = "GNU General Public License v3.0"
+__version__ = "1.0"
+__maintainer__ = "Idris Hayward"
+__email__ = "CaderIdrisGH@outlook.com"
+__status__ = "Stable Release"
+
+import calendar
+import datetime as dt
+
+# Location fields are copied to aggregates unchanged rather than aggregated
+from modules.aurn import LOCATION_FIELDS, is_measurement
+
+# Tag suffixes that describe a single hourly measurement, not an aggregate.
+# The method tag suffix is set in config so is passed in separately
+MEASUREMENT_TAG_SUFFIXES = [
+        " status",
+        " unit"
+        ]
+
+
+def aggregate_container_list(list_of_containers, period="daily",
+                             min_capture=0.75,
+                             measurement="air_quality_daily",
+                             method_tag="method"):
+    """ Aggregates hourly containers to daily or monthly containers
+
+    Measurements are grouped by the day (or month) of the hour they end.
+    As AURN timestamps mark the end of the hour measured, midnight belongs
+    to the previous day. For each pollutant the mean and maximum of the
+    period are calculated, but only if the fraction of hours with a
+    measurement is at least min_capture.
+
+    Keyword arguments:
+        list_of_containers (list): Hourly containers for one site, in the
+        format used by InfluxWriter
+
+        period (str): "daily" or "monthly"
+
+        min_capture (float): Fraction of hours in the period that need a
+        measurement for the aggregate to be valid, 0.75 by default
+
+        measurement (str): Name of the measurement the aggregates are
+        exported to
+
+        method_tag (str): Suffix of the "{pollutant} {method_tag}" tags,
+        "Method Tag" from config. The instrument can change within a
+        period, so these tags are removed along with status and unit tags
+
+    Variables:
+        periods (dict): Hourly values of each pollutant, keyed by the start
+        of the period
+
+        expected_hours (int): Number of hours in the period
+
+        capture (float): Fraction of expected hours with a measurement
+
+    Returns:
+        List of containers, one per period, timestamped at the start of
+        the period with "{pollutant} mean", "{pollutant} max" and
+        "{pollutant} capture" fields. "period_start" is True so file
+        exports partition them by the period they cover
+    """
+    tag_suffixes = list(MEASUREMENT_TAG_SUFFIXES)
+    if method_tag != "":
+        tag_suffixes.append(f" {method_tag}")
+    periods = dict()
+    period_metadata = dict()
+    for container in list_of_containers:
+        hour_start = container["time"] - dt.timedelta(hours=1)
+        if period == "monthly":
+            period_start = hour_start.replace(
+                    day=1, hour=0, minute=0, second=0, microsecond=0
+                    )
+        else:
+            period_start = hour_start.replace(
+                    hour=0, minute=0, second=0, microsecond=0
+                    )
+        pollutant_values = periods.setdefault(period_start, dict())
+        if period_start not in period_metadata:
+            period_metadata[period_start] = container
+        for field, value in container["fields"].items():
+            if not is_measurement(field, value):
+                continue
+            pollutant_values.setdefault(field, list()).append(value)
+
+    aggregate_containers = list()
+    for period_start, pollutant_values in sorted(periods.items()):
+        if period == "monthly":
+            expected_hours = 24 * calendar.monthrange(
+                    period_start.year, period_start.month
+                    )[1]
+        else:
+            expected_hours = 24
+        fields = dict()
+        for pollutant, values in pollutant_values.items():
+            capture = len(values) / expected_hours
+            if capture < min_capture:
+                continue
+            fields[f"{pollutant} mean"] = sum(values) / len(values)
+            fields[f"{pollutant} max"] = max(values)
+            fields[f"{pollutant} capture"] = capture
+        if len(fields) == 0:
+            continue
+        first_container = period_metadata[period_start]
+        for field, value in first_container["fields"].items():
+            if field in LOCATION_FIELDS:
+                fields[field] = value
+        aggregate_containers.append(
+                {
+                    "time": period_start,
+                    "measurement": measurement,
+                    "tags": {
+                        tag: value for tag, value in
+                        first_container["tags"].items()
+                        if not any(tag.endswith(suffix) for suffix in
+                                   tag_suffixes)
+                        },
+                    "fields": fields,
+                    "period_start": True
+                }
+            )
+    return aggregate_containers
+
+
+class SiteStatistics:
+    """ Minimum, maximum, mean and count of each pollutant measured at a site
+    over the whole export
+
+    Containers are added a year at a time as they are exported, so the
+    statistics cover exactly what was written. Missing measurements aren't
+    in the containers so aren't counted
+
+    Attributes:
+        metadata (dict): Metadata for the site
+
+        pollutants (dict): Running min, max, sum and count for each
+        pollutant
+
+        last_time (datetime): Time of the latest measurement added
+
+    Methods:
+        add_container_list: Add exported containers to the statistics
+
+        as_dict: Return the statistics as a json serialisable dict
+
+        as_container: Return the statistics as a container for export
+    """
+    def __init__(self, metadata):
+        """ Initialises class
+
+        Keyword arguments:
+            metadata (dict): Metadata for the site, with "tags" and "fields"
+        """
+        self.metadata = metadata
+        self.pollutants = dict()
+        self.last_time = None
+
+    def add_container_list(self, list_of_containers):
+        """ Add exported containers to the statistics
+
+        Keyword arguments:
+            list_of_containers (list): Containers for the site, in the
+            format used by InfluxWriter
+        """
+        for container in list_of_containers:
+            if self.last_time is None or container["time"] > self.last_time:
+                self.last_time = container["time"]
+            for field, value in container["fields"].items():
+                if not is_measurement(field, value):
+                    continue
+                stats = self.pollutants.setdefault(
+                        field,
+                        {"min": value, "max": value, "sum": 0, "count": 0}
+                        )
+                stats["min"] = min(stats["min"], value)
+                stats["max"] = max(stats["max"], value)
+                stats["sum"] += value
+                stats["count"] += 1
+
+    def as_dict(self):
+        """ Return the statistics as a json serialisable dict
+
+        Returns:
+            Dict with the site's name and download code and a dict of
+            min, max, mean and count for each pollutant
+        """
+        return {
+                "Site Name": self.metadata["tags"]["Site Name"],
+                "Download Code": str(self.metadata["tags"]["Download Code"]),
+                "pollutants": {
+                    pollutant: {
+                        "min": stats["min"],
+                        "max": stats["max"],
+                        "mean": stats["sum"] / stats["count"],
+                        "count": stats["count"]
+                        }
+                    for pollutant, stats in self.pollutants.items()
+                    }
+                }
+
+    def as_container(self, measurement="site_stats"):
+        """ Return the statistics as a container for export
+
+        Keyword arguments:
+            measurement (str): Name of the measurement the statistics are
+            exported to
+
+        Returns:
+            Container timestamped at the latest measurement with
+            "{pollutant} min", "{pollutant} max", "{pollutant} mean" and
+            "{pollutant} count" fields, None if nothing was added
+        """
+        if len(self.pollutants) == 0:
+            return None
+        fields = dict()
+        for pollutant, stats in self.as_dict()["pollutants"].items():
+            for statistic, value in stats.items():
+                fields[f"{pollutant} {statistic}"] = float(value)
+        return {
+                "time": self.last_time,
+                "measurement": measurement,
+                "tags": self.metadata["tags"].copy(),
+                "fields": fields
+                }
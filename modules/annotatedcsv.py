@@ -0,0 +1,266 @@
+""" Contains classes and methods that write data to InfluxDB annotated csvs
+
+Instead of sending data to an InfluxDB database directly, measurements are
+saved as annotated csv files which can be bulk loaded using the influx CLI
+(influx write --format csv -f file.csv). This allows data to be moved in
+to InfluxDB on machines with no network access to the DEFRA website. It
+accepts the same list of containers as InfluxWriter
+
+    Classes:
+        AnnotatedCSVWriter: Writes containers to InfluxDB annotated csvs
+
+    Functions:
+        validate_timestamp_format: Checks a timestamp format from config is
+        usable
+
+        format_timestamp: Formats a measurement time
+
+"""
+
+__author__ = "Idris Hayward"
+__copyright__ = "2021, Idris Hayward"
+__credits__ = ["Idris Hayward"]
+__license__ = "GNU General Public License v3.0"
+__version__ = "1.0"
+__maintainer__ = "Idris Hayward"
+__email__ = "CaderIdrisGH@outlook.com"
+__status__ = "Stable Release"
+
+import csv
+import datetime as dt
+import os
+
+from modules.lineprotocol import timestamp_nanoseconds
+from modules.partition import partition_container_list, validate_partition
+
+# Annotation datatype of the time column for each named timestamp format.
+# Any other format is a strftime format string and written as a string
+TIMESTAMP_DATATYPES = {
+        "RFC3339": "dateTime:RFC3339",
+        "Epoch": "dateTime:number"
+        }
+
+
+def validate_timestamp_format(timestamp_format):
+    """ Checks a timestamp format from config is usable
+
+    Keyword arguments:
+        timestamp_format (str): RFC3339, Epoch or a strftime format string
+        e.g %Y-%m-%d %H:%M:%S
+
+    Returns:
+        timestamp_format unchanged
+
+    Raises:
+        ValueError if the format is not RFC3339, Epoch or a strftime format
+        string containing at least one directive
+    """
+    if timestamp_format in TIMESTAMP_DATATYPES:
+        return timestamp_format
+    if not isinstance(timestamp_format, str) or "%" not in timestamp_format:
+        raise ValueError(
+            f'"{timestamp_format}" is not a valid timestamp format, use '
+            f"RFC3339, Epoch or a strftime format e.g %Y-%m-%d %H:%M:%S"
+        )
+    try:
+        dt.datetime(2000, 1, 1).strftime(timestamp_format)
+    except ValueError:
+        raise ValueError(
+            f'"{timestamp_format}" is not a valid strftime format'
+        )
+    return timestamp_format
+
+
+def format_timestamp(timestamp, timestamp_format="RFC3339"):
+    """ Formats a measurement time
+
+    Times without a timezone are treated as UTC, times with one are
+    converted to UTC
+
+    Keyword arguments:
+        timestamp (datetime): Time to format
+
+        timestamp_format (str): RFC3339 (e.g 2021-01-01T01:00:00Z), Epoch
+        (nanoseconds since 1970-01-01, the precision influx write expects
+        for dateTime:number) or a strftime format string
+
+    Returns:
+        Formatted time as str
+    """
+    if timestamp.tzinfo is not None:
+        timestamp = timestamp.astimezone(dt.timezone.utc).replace(tzinfo=None)
+    if timestamp_format == "RFC3339":
+        return f"{timestamp.isoformat()}Z"
+    if timestamp_format == "Epoch":
+        return str(timestamp_nanoseconds(timestamp))
+    return timestamp.strftime(timestamp_format)
+
+
+class AnnotatedCSVWriter:
+    """ Writes containers to InfluxDB annotated csvs
+
+    One file is written per measurement per site for the run, named after
+    the measurement and download code. If "File Partition" is Year or Month
+    the containers are instead split by the year or month they measure,
+    each written to a file named after its partition. Each list of
+    containers is written as a table starting with #datatype, #group and
+    #default annotation rows followed by the header. A file is emptied the
+    first time it is written to in a run, later tables are added after a
+    blank line so earlier years aren't overwritten
+
+    Attributes:
+        config (dict): Contains the directory to save files to
+
+        directory (str): Directory annotated csvs are saved to
+
+        timestamp_format (str): Format of the time column, "Timestamp
+        Format" from config (default RFC3339)
+
+        partition (str): None, Year or Month, "File Partition" from config
+        (default None)
+
+        written_paths (set): Paths of files written this run
+
+    Methods:
+        annotation_rows: Generates annotation and header rows for a list
+        of containers
+
+        container_list_to_rows: Converts list of containers to annotated
+        csv rows
+
+        write_container_list: Writes list of containers to an annotated
+        csv
+    """
+    def __init__(self, csv_config):
+        """ Initialises class and makes the output directory if needed
+
+            Keyword arguments:
+                csv_config (dict): The following keys are required:
+                    - "Annotated CSV Directory": Directory to save
+                                                 annotated csvs to
+
+            Raises:
+                ValueError if "Timestamp Format" or "File Partition" in the
+                config is invalid
+        """
+        self.config = csv_config
+        self.directory = self.config["Annotated CSV Directory"]
+        self.timestamp_format = validate_timestamp_format(
+                self.config.get("Timestamp Format", "RFC3339")
+                )
+        self.partition = validate_partition(
+                self.config.get("File Partition", "None")
+                )
+        self.written_paths = set()
+        os.makedirs(self.directory, exist_ok=True)
+
+    def annotation_rows(self, tag_columns, field_columns):
+        """ Generates annotation and header rows for a list of containers
+
+        Keyword arguments:
+            tag_columns (list): Names of the tag columns
+
+            field_columns (dict): Names of the field columns, values are
+            their datatype (double or string)
+
+        Returns:
+            List of rows: #datatype, #group, #default and the header
+        """
+        datatypes = (
+                ["measurement"] +
+                ["tag" for tag in tag_columns] +
+                list(field_columns.values()) +
+                [TIMESTAMP_DATATYPES.get(self.timestamp_format, "string")]
+                )
+        header = (
+                ["measurement"] +
+                tag_columns +
+                list(field_columns.keys()) +
+                ["time"]
+                )
+        # The annotation name shares the first cell with the annotation of
+        # the first column, separated by a space
+        return [
+                [f"#datatype {datatypes[0]}"] + datatypes[1:],
+                ["#group false"] + ["false" for column in header[1:]],
+                ["#default"] + ["" for column in header[1:]],
+                header
+                ]
+
+    def container_list_to_rows(self, list_of_containers):
+        """ Converts list of containers to annotated csv rows
+
+        The tags and fields in each container can differ, so the columns
+        are the union of all of them. Missing tags and fields are left
+        blank
+
+        Keyword arguments:
+            list_of_containers (list): Containers in the format used by
+            InfluxWriter
+
+        Variables:
+            tag_columns (list): Names of all tags in the containers
+
+            field_columns (dict): Names of all fields in the containers,
+            values are their datatype
+
+        Returns:
+            List of rows, starting with annotations and header
+        """
+        tag_columns = list()
+        field_columns = dict()
+        for container in list_of_containers:
+            for tag in container["tags"].keys():
+                if tag not in tag_columns:
+                    tag_columns.append(tag)
+            for field, value in container["fields"].items():
+                if field not in field_columns:
+                    field_columns[field] = (
+                            "string" if isinstance(value, str) else "double"
+                            )
+        rows = self.annotation_rows(tag_columns, field_columns)
+        for container in list_of_containers:
+            rows.append(
+                    [container["measurement"]] +
+                    [container["tags"].get(tag, "") for tag in tag_columns] +
+                    [container["fields"].get(field, "")
+                     for field in field_columns.keys()] +
+                    [format_timestamp(container["time"],
+                                      self.timestamp_format)]
+                    )
+        return rows
+
+    def write_container_list(self, list_of_containers):
+        """ Writes list of containers to an annotated csv
+
+        The file is named {Measurement}_{Download Code}.csv using the
+        first container, with spaces in the measurement replaced by
+        underscores. If partitioned, the partition name is added (e.g
+        {Measurement}_{Download Code}_2019-01.csv) and one file is written
+        per partition
+
+        Keyword arguments:
+            list_of_containers (list): Containers to write
+        """
+        if len(list_of_containers) == 0:
+            return
+        partitions = partition_container_list(
+                list_of_containers, self.partition
+                )
+        for name, containers in partitions.items():
+            first_container = containers[0]
+            file_name = (
+                    f'{first_container["measurement"].replace(" ", "_")}_'
+                    f'{first_container["tags"].get("Download Code", "AURN")}'
+                    )
+            if name is not None:
+                file_name = f"{file_name}_{name}"
+            csv_path = os.path.join(self.directory, f"{file_name}.csv")
+            rows = self.container_list_to_rows(containers)
+            mode = "w"
+            if csv_path in self.written_paths:
+                mode = "a"
+                rows = [[]] + rows
+            self.written_paths.add(csv_path)
+            with open(csv_path, mode, newline="") as csv_file:
+                csv.writer(csv_file).writerows(rows)
@@ -7,11 +7,63 @@ handles all communications with the DEFRA website, obtaining metadata and
 measurements
 
     Classes:
+        AURNRequestError: A request to the DEFRA website failed
+
+        AURNRateLimitedError: The DEFRA website kept rate limiting or
+        challenging requests
+
+        SiteCode: Validated download code for an AURN site
+
+        MetadataQuery: Builds the query used to search for AURN sites
+
         AURNAPI: Handles communication with the AURN/DEFRA website to get
         metadata and measurements
 
     Functions:
         remove_brackets: Removes brackets and their contents from a string
+
+        compile_xpath: Compiles an XPath string, raising a clear error if it
+        is invalid
+
+        validate_patterns: Compiles every XPath in the config, returning all
+        that are invalid
+
+        format_row: Formats a metadata row as an aligned block of text
+
+        deduplicate_columns: Suffixes repeated column names with a number
+
+        instrument_method: Gets the instrument/method from a column name
+
+        decode_entities: Decodes HTML entities in a metadata value
+
+        validate_domain: Checks a domain from config uses http or https
+
+        validate_year: Checks a year is within the range measurements can be
+        downloaded for
+
+        parse_metadata_date: Reads a Start Date or End Date from the
+        metadata csv
+
+        is_measurement: Checks if a container field is a pollutant
+        measurement
+
+        count_measurements: Counts the pollutant measurements in a list of
+        containers
+
+        status_allowed: Checks if a measurement's status is one of the
+        allowed statuses
+
+        metadata_cache_status: Reports the age of a metadata cache file and
+        whether it is past its TTL
+
+        retry_after_seconds: Reads the seconds to wait from a Retry-After
+        header
+
+        response_content: Gets the body of a response, decompressing it if
+        it is still gzipped
+
+        request_headers: Headers sent with every request to the DEFRA
+        website
 """
 
 __author__ = "Idris Hayward"
@@ -24,11 +76,152 @@ __email__ = "CaderIdrisGH@outlook.com"
 __status__ = "Stable Release"
 
 import requests as req
+import urllib3  # Errors raised reading a streamed response body
 from lxml import html  # Needed to scrape AURN website for metadata
+from lxml import etree  # Needed to compile XPaths before scraping
 import pandas as pd
 import datetime as dt
-import urllib  # Needed for pandas error when csv not present
+from html import unescape  # Needed to decode HTML entities in metadata
+import re  # Needed to strip duplicate column suffixes
+from zoneinfo import ZoneInfo  # Needed to convert GMT to UK local time
 from collections import defaultdict  # Easier to work with that dict
+from collections import deque  # Queue of csvs being downloaded
+from urllib.parse import urlparse  # Needed to check domain schemes
+from urllib.parse import urlencode  # Needed to build metadata queries
+from concurrent.futures import ThreadPoolExecutor  # Parallel scraping
+import threading
+from email.utils import parsedate_to_datetime  # Needed for Retry-After
+import gzip  # Needed for pages gzipped without a Content-Encoding header
+import hashlib  # Needed to key the metadata cache
+import io
+import json
+import logging
+import os
+import time
+
+from modules.ratelimit import RateLimiter
+from modules.retry import RetryPolicy
+
+logger = logging.getLogger(__name__)
+
+# Added to the user agent so DEFRA can tell requests come from this exporter
+EXPORTER_USER_AGENT = f"AURN-InfluxDB-Export/{__version__}"
+
+# Config keys AURNAPI can't work without
+REQUIRED_CONFIG_KEYS = [
+        "AURN Domain",
+        "AURN Metadata Search",
+        "AURN Site Info",
+        "AURN Site Info Provider",
+        "AURN Site Code Link",
+        "AURN Data Link",
+        "XPath to CSV",
+        "XPath to Code",
+        "User Agent"
+        ]
+
+# Config keys containing XPaths used to scrape the DEFRA website
+XPATH_CONFIG_KEYS = [
+        "XPath to CSV",
+        "XPath to Code"
+        ]
+
+# Traffic context metadata only present for some (mostly roadside) sites.
+# Keys are the name used in the exported metadata, values are the metadata
+# csv columns it may be found in
+OPTIONAL_METADATA_TAGS = {
+        "Road Type": ["Road Type", "Type of Road"],
+        "Traffic Flow": ["Traffic Flow", "Annual Average Daily Flow"]
+        }
+OPTIONAL_METADATA_FIELDS = {
+        "Distance To Kerb": [
+            "Distance to kerb (m)",
+            "Distance from kerb (m)",
+            "Distance to kerb"
+            ],
+        "Distance To Road": [
+            "Distance to road (m)",
+            "Distance from road (m)",
+            "Distance to road"
+            ]
+        }
+
+# Numeric fields added to containers by csv_to_json_list that describe the
+# site or export rather than being measurements. Every writer and helper
+# that needs to tell measurements apart uses is_measurement
+LOCATION_FIELDS = [
+        "Latitude",
+        "Longitude",
+        "Northing",
+        "Easting",
+        "Altitude",
+        "Distance To Kerb",
+        "Distance To Road",
+        "source_year"
+        ]
+
+# Config keys that change which sites are found or what metadata is kept,
+# hashed with the years to key the metadata cache. Credentials and
+# settings that only affect the measurement download are left out
+METADATA_CACHE_CONFIG_KEYS = [
+        "AURN Domain",
+        "AURN Mirror Domains",
+        "AURN Metadata Search",
+        "AURN Metadata JSON",
+        "AURN Site Info",
+        "AURN Site Info Provider",
+        "AURN Site Code Link",
+        "Metadata Backend",
+        "Metadata Columns",
+        "Metadata Date Formats",
+        "XPath to CSV",
+        "XPath to Code"
+        ]
+
+# Metadata columns that may list the pollutants a site measures, separated
+# by commas or semicolons
+POLLUTANT_LIST_COLUMNS = [
+        "Pollutants",
+        "Pollutants Measured",
+        "Parameters"
+        ]
+
+# Metadata columns that may hold the region or country a site is in, the
+# first present is added as the "Region" tag
+REGION_COLUMNS = [
+        "Government Region",
+        "Region",
+        "Country"
+        ]
+
+# Formats of the Start Date and End Date metadata columns, tried in order.
+# DEFRA has used both ISO and UK style dates
+DEFAULT_METADATA_DATE_FORMATS = [
+        "%Y-%m-%d",
+        "%d/%m/%Y"
+        ]
+
+# Strings found in rate limit and bot challenge pages served instead of
+# the page requested, compared case insensitively against HTML responses.
+# Captchas are matched by the element they are embedded with, as pages
+# can mention captchas without being a challenge
+DEFAULT_CHALLENGE_MARKERS = [
+        'class="g-recaptcha"',
+        'class="h-captcha"',
+        "challenge-platform",
+        "just a moment...",
+        "too many requests"
+        ]
+
+# Strings DEFRA has used in measurement csvs to show data is missing,
+# compared case insensitively after surrounding whitespace is removed
+DEFAULT_MISSING_SENTINELS = [
+        "",
+        "no data",
+        "-",
+        "n/a",
+        "nan"
+        ]
 
 
 def remove_brackets(string_with_brackets):
@@ -50,103 +243,1848 @@ def remove_brackets(string_with_brackets):
     Returns:
         Input string with brackets removes
 
-    """
+    """
+
+    bracket_pairs = {
+            "(": ")",
+            "[": "]",
+            "{": "}",
+            "<": ">"
+            }
+    open_brackets = list(bracket_pairs.keys())
+
+    clean_string = ""
+    unbracketed = True
+    close_bracket = ""
+    for character in string_with_brackets:
+        if character in open_brackets:
+            unbracketed = False
+            close_bracket = bracket_pairs[character]
+        if unbracketed:
+            clean_string = f"{clean_string}{character}"
+        if not unbracketed and character == close_bracket:
+            unbracketed = True
+
+    # Sometimes removing brackets leaves trailing spaces. This removes them.
+    while clean_string[-1] == " ":
+        clean_string = clean_string[:-1]
+
+    return clean_string
+
+
+def compile_xpath(xpath_string):
+    """ Compiles an XPath string, raising a clear error if it is invalid
+
+    The XPaths used to scrape the DEFRA website are read from the config.
+    Compiling them before any scraping starts means a mistyped XPath is
+    reported straight away instead of partway through a download.
+
+    Keyword arguments:
+        xpath_string (str): XPath to compile
+
+    Returns:
+        Compiled lxml XPath object, called with an html object to search it
+
+    Raises:
+        ValueError if the XPath is invalid, including the offending XPath
+    """
+    try:
+        return etree.XPath(xpath_string)
+    except etree.XPathSyntaxError as xpath_error:
+        raise ValueError(
+            f'"{xpath_string}" is not a valid XPath: {xpath_error}'
+        )
+
+
+def validate_patterns(config):
+    """ Compiles every XPath in the config, returning all that are invalid
+
+    compile_xpath stops at the first invalid XPath. Checking them all at
+    once means a misconfigured file can be fixed in one go
+
+    Keyword arguments:
+        config (dict): Config, XPaths missing from it are skipped
+
+    Returns:
+        List of (config key, error message) tuples, empty if every XPath
+        is valid
+    """
+    pattern_errors = list()
+    for xpath_key in XPATH_CONFIG_KEYS:
+        if xpath_key not in config:
+            continue
+        try:
+            compile_xpath(config[xpath_key])
+        except ValueError as xpath_error:
+            pattern_errors.append((xpath_key, str(xpath_error)))
+    return pattern_errors
+
+
+def deduplicate_columns(column_names):
+    """ Suffixes repeated column names with a number so they are unique
+
+    Some AURN csvs measure the same pollutant with two instruments, which
+    only differ by the bracketed instrument name (e.g PM10 particulate
+    matter (FIDAS)). Once the brackets are removed they share a name and
+    all but one would be lost when the csv is reformatted. The first
+    column keeps its name, later ones get " 2", " 3" etc appended.
+
+    Keyword arguments:
+        column_names (list): Column names, possibly with repeats
+
+    Variables:
+        name_counts (dict): Number of times each name has been seen
+
+    Returns:
+        List of column names with repeats suffixed, same order as input
+    """
+    name_counts = dict()
+    unique_names = list()
+    for column in column_names:
+        name_counts[column] = name_counts.get(column, 0) + 1
+        if name_counts[column] == 1:
+            unique_names.append(column)
+            continue
+        unique_name = f"{column} {name_counts[column]}"
+        while unique_name in column_names or unique_name in unique_names:
+            name_counts[column] += 1
+            unique_name = f"{column} {name_counts[column]}"
+        unique_names.append(unique_name)
+    return unique_names
+
+
+def instrument_method(column_name):
+    """ Gets the instrument/method from a measurement column name
+
+    Some AURN csvs put the instrument or measurement method in round
+    brackets after the pollutant (e.g PM10 particulate matter (FIDAS)).
+    This is removed by remove_brackets, so it's read out first.
+
+    Keyword arguments:
+        column_name (str): Column name from measurement csv
+
+    Variables:
+        methods (list): Contents of all round brackets in the name
+
+    Returns:
+        Contents of the last round brackets, None if there are none
+    """
+    methods = re.findall(r"\(([^()]*)\)", column_name)
+    if len(methods) == 0 or methods[-1].strip() == "":
+        return None
+    return methods[-1].strip()
+
+
+def decode_entities(value):
+    """ Decodes HTML entities in a metadata value
+
+    Some metadata values contain HTML entities (e.g &amp; or &#176;)
+    which would otherwise end up in tags. Non string values are returned
+    unchanged.
+
+    Keyword arguments:
+        value: Metadata value
+
+    Returns:
+        Value with HTML entities decoded if it's a string, value otherwise
+    """
+    if isinstance(value, str):
+        return unescape(value)
+    return value
+
+
+def validate_domain(domain):
+    """ Checks a domain from config uses http or https
+
+    The scheme is taken from the config as given, so local mirrors can be
+    used over plain http (e.g http://localhost:1234/). Trailing slashes
+    are removed as the paths from config start with one
+
+    Keyword arguments:
+        domain (str): Domain from config, including scheme
+
+    Returns:
+        Domain without trailing slashes
+
+    Raises:
+        ValueError if the scheme is not http or https
+    """
+    if urlparse(domain).scheme not in ["http", "https"]:
+        raise ValueError(
+            f'"{domain}" is not a valid domain, it must start with http:// '
+            f"or https://"
+        )
+    return domain.rstrip("/")
+
+
+def validate_year(year, min_year, max_year):
+    """ Checks a year is within the range measurements can be downloaded
+    for
+
+    A typo in a year (e.g 219 or 20199) would otherwise build a url for a
+    csv that can't exist and waste a request finding out
+
+    Keyword arguments:
+        year (int): Year to check
+
+        min_year (int): Earliest year allowed
+
+        max_year (int): Latest year allowed
+
+    Returns:
+        Year as an int
+
+    Raises:
+        ValueError if the year is outside min_year to max_year
+    """
+    year = int(year)
+    if not min_year <= year <= max_year:
+        raise ValueError(
+            f"{year} is not a valid year, it must be between {min_year} and "
+            f"{max_year}. The range can be changed with Min Year and "
+            f"Max Year in the config"
+        )
+    return year
+
+
+def parse_metadata_date(value, column, date_formats):
+    """ Reads a Start Date or End Date from the metadata csv
+
+    Keyword arguments:
+        value (str): Date from the metadata csv
+
+        column (str): Name of the column the date is from, used in the
+        error message
+
+        date_formats (list): strptime formats to try, in order
+
+    Returns:
+        datetime from the first format that matches
+
+    Raises:
+        ValueError if no format matches, so the site is dropped with a
+        warning rather than treated as active for every year
+    """
+    value = str(value).strip()
+    for date_format in date_formats:
+        try:
+            return dt.datetime.strptime(value, date_format)
+        except ValueError:
+            continue
+    raise ValueError(
+        f'{column} "{value}" doesn\'t match any of the Metadata Date '
+        f"Formats ({', '.join(date_formats)})"
+    )
+
+
+def format_row(row):
+    """ Formats a metadata row as an aligned block of text
+
+    Used to show the full metadata of a site when it is dropped or fails,
+    making it easier to work out why. Keys are sorted and values are lined
+    up after the longest key.
+
+    Keyword arguments:
+        row (dict or Series): Metadata row to format
+
+    Variables:
+        key_length (int): Length of the longest key
+
+    Returns:
+        Multiline string with one key: value pair per line
+    """
+    row = dict(row)
+    if len(row) == 0:
+        return ""
+    key_length = max(len(str(key)) for key in row.keys())
+    return "\n".join(
+            f"{str(key).ljust(key_length)}: {row[key]}"
+            for key in sorted(row.keys(), key=str)
+            )
+
+
+def is_measurement(field, value):
+    """ Checks if a container field is a pollutant measurement
+
+    Location fields (LOCATION_FIELDS) and string fields (e.g local_time or
+    a unit) aren't measurements
+
+    Keyword arguments:
+        field (str): Name of the field
+
+        value: Value of the field
+
+    Returns:
+        True if the field is a measurement
+    """
+    return field not in LOCATION_FIELDS and not isinstance(value, str)
+
+
+def count_measurements(list_of_containers):
+    """ Counts the pollutant measurements in a list of containers
+
+    Each field that is_measurement accepts is one measurement
+
+    Keyword arguments:
+        list_of_containers (list): Containers in the format made by
+        AURNAPI.csv_to_json_list
+
+    Returns:
+        Number of measurements as int
+    """
+    return sum(
+            1 for container in list_of_containers
+            for field, value in container["fields"].items()
+            if is_measurement(field, value)
+            )
+
+
+def status_allowed(status, allowed_statuses):
+    """ Checks if a measurement's status is one of the allowed statuses
+
+    AURN statuses start with V for verified (ratified) measurements or P
+    for provisional measurements that may still change, followed by the
+    unit in some older csvs (e.g "V ugm-3"). Statuses are compared by how
+    they start so both forms match, not case sensitive
+
+    Keyword arguments:
+        status (str): Status of the measurement, None or NaN if it has none
+
+        allowed_statuses (list): Statuses to allow e.g ["V"]. Every status
+        is allowed if empty
+
+    Returns:
+        True if the measurement should be kept. A measurement with no
+        status is only kept if allowed_statuses is empty
+    """
+    if len(allowed_statuses) == 0:
+        return True
+    status = str(status).strip().upper()
+    if status in ["", "NAN", "NONE"]:
+        return False
+    return any(
+            status.startswith(str(allowed).strip().upper())
+            for allowed in allowed_statuses
+            )
+
+
+def metadata_cache_status(cache_path, ttl_days):
+    """ Reports the age of a metadata cache file and whether it is past its
+    TTL
+
+    Only the file's modification time is read, the cache isn't loaded or
+    checked against the config. A missing cache is reported as stale as
+    the metadata will be downloaded on the next run
+
+    Keyword arguments:
+        cache_path (str): Path of the cache json file
+
+        ttl_days (float): Days the cache can be used for after it's saved
+
+    Returns:
+        dict with the "path", the time it was "saved" (UTC, None if
+        missing), its "age" in days (None if missing) and whether it is
+        "stale"
+    """
+    try:
+        saved = os.path.getmtime(cache_path)
+    except OSError:
+        return {"path": cache_path, "saved": None, "age": None,
+                "stale": True}
+    age = (time.time() - saved) / 86400
+    return {
+            "path": cache_path,
+            "saved": dt.datetime.fromtimestamp(saved, dt.timezone.utc),
+            "age": age,
+            "stale": age > ttl_days
+            }
+
+
+class AURNRequestError(Exception):
+    """ A request to the DEFRA website failed
+
+    Carries the HTTP status code so a missing file (404) can be told apart
+    from a server error (5xx) or no response at all (timeout, dropped
+    connection) without matching on the error message
+
+    Attributes:
+        url (str): Url that was requested
+
+        status_code (int): HTTP status code returned, None if there was no
+        response
+
+        reason (str): Reason given with the status code, or the error
+        raised if there was no response
+
+    Methods:
+        is_retryable: Could the request succeed if sent again or to a
+        mirror?
+    """
+    def __init__(self, url, status_code=None, reason=""):
+        """ Initialises class
+
+        Keyword arguments:
+            url (str): Url that was requested
+
+            status_code (int): HTTP status code returned, None if there was
+            no response
+
+            reason (str): Reason for the failure
+        """
+        self.url = url
+        self.status_code = status_code
+        self.reason = reason
+        if status_code is None:
+            super().__init__(f"No response from {url}: {reason}")
+        else:
+            super().__init__(f"{url} returned {status_code} {reason}")
+
+    def is_retryable(self):
+        """ Could the request succeed if sent again or to a mirror?
+
+        Server errors (5xx), rate limiting (429) and requests with no
+        response are worth trying again. Other client errors (e.g 404) will
+        fail the same way every time
+
+        Returns:
+            True if the request should be retried
+        """
+        return (self.status_code is None or self.status_code == 429 or
+                self.status_code >= 500)
+
+
+class AURNRateLimitedError(AURNRequestError):
+    """ The DEFRA website kept rate limiting or challenging requests
+
+    Raised once a request has been answered with a 429 or a challenge page
+    (e.g a captcha) more times than "Rate Limit Retries" allows. The status
+    code is 429 for challenge pages too, as they mean the same thing
+    """
+
+
+def retry_after_seconds(retry_after, default):
+    """ Reads the seconds to wait from a Retry-After header
+
+    HTTP dates are always GMT, a date without a timezone (e.g one ending
+    in -0000) is treated as UTC
+
+    Keyword arguments:
+        retry_after (str): Value of the header, either a number of seconds
+        or an HTTP date. None if the header wasn't sent
+
+        default (float): Seconds to wait if the header is missing or can't
+        be read
+
+    Returns:
+        Seconds to wait as a float, never negative
+    """
+    if retry_after is None:
+        return default
+    try:
+        return max(float(retry_after), 0)
+    except ValueError:
+        pass
+    try:
+        retry_time = parsedate_to_datetime(retry_after)
+    except (TypeError, ValueError):
+        return default
+    if retry_time.tzinfo is None:
+        retry_time = retry_time.replace(tzinfo=dt.timezone.utc)
+    return max(
+            (retry_time - dt.datetime.now(dt.timezone.utc)).total_seconds(), 0
+            )
+
+
+def response_content(response):
+    """ Gets the body of a response, decompressing it if it is still
+    gzipped
+
+    requests decompresses responses sent with a gzip Content-Encoding
+    header. If a page is gzipped without the header, the body is binary
+    and the XPaths silently find nothing, so it is decompressed here
+    instead
+
+    Keyword arguments:
+        response (requests.Response): Response to get the body of
+
+    Returns:
+        Body of the response as bytes
+    """
+    content = response.content
+    if content[:2] == b"\x1f\x8b":
+        try:
+            return gzip.decompress(content)
+        except (OSError, EOFError):
+            return content
+    return content
+
+
+def request_headers(config):
+    """ Headers sent with every request to the DEFRA website
+
+    The exporter name and version are added to "User Agent" from the
+    config. DEFRA rejects requests without a browser-like user agent, so
+    it is kept at the start. If "Contact Email" is in the config it is
+    added to the user agent and sent in the From header, so DEFRA can get
+    in touch if the exporter causes problems rather than blocking it
+
+    Keyword arguments:
+        config (dict): Contains "User Agent" and optionally "Contact Email"
+
+    Returns:
+        Dict of headers
+    """
+    user_agent = f"{config['User Agent']} {EXPORTER_USER_AGENT}".strip()
+    contact_email = str(config.get("Contact Email", "")).strip()
+    headers = {"User-Agent": user_agent}
+    if contact_email != "":
+        headers["User-Agent"] = f"{user_agent} (+mailto:{contact_email})"
+        headers["From"] = contact_email
+    return headers
+
+
+class SiteCode(str):
+    """ Validated download code for an AURN site
+
+    The download code (e.g ABD for Aberdeen) is used to build measurement
+    csv urls and is easy to confuse with the UK-AIR ID (e.g UKA00399).
+    Wrapping it in this class checks it looks like a download code: 1 to
+    8 letters and numbers. It behaves like a normal string otherwise.
+
+    Raises:
+        ValueError if the code isn't a string, is empty or contains
+        anything other than letters and numbers
+    """
+    def __new__(cls, code):
+        """ Validates and creates site code
+
+        Keyword arguments:
+            code (str): Download code to validate
+        """
+        if not isinstance(code, str):
+            raise ValueError(f"{code} is not a valid site code, not a string")
+        code = code.strip()
+        if re.fullmatch(r"[A-Za-z0-9]{1,8}", code) is None:
+            raise ValueError(
+                f'"{code}" is not a valid site code, expected 1 to 8 '
+                f"letters and numbers"
+            )
+        return super().__new__(cls, code)
+
+
+class MetadataQuery:
+    """ Builds the query used to search for AURN sites
+
+    The DEFRA site search takes a query string where 9999 (ANY) means any
+    value is accepted. The default query matches "AURN Metadata Search" in
+    the example config: every open and closed site in the AURN (group 4).
+    Each setter returns the query so they can be chained, e.g
+    MetadataQuery().include_closed(False).region_id(3)
+
+    Attributes:
+        path (str): Path of the site search page
+
+        parameters (dict): Query string parameters, in the order DEFRA
+        expects them
+
+    Methods:
+        group_id: Set the monitoring network to search
+
+        include_closed: Set whether closed sites are included
+
+        country_id: Set the country to search
+
+        region_id: Set the region to search
+
+        location_type: Set the environment type to search
+
+        pollutant: Set the pollutant sites must measure
+
+        urn: Generate the path and query string of the search
+    """
+    ANY = 9999
+
+    def __init__(self, path="/networks/find-sites"):
+        """ Initialises class with a query for every AURN site
+
+        Keyword arguments:
+            path (str): Path of the site search page
+        """
+        self.path = path
+        self.parameters = {
+                "site_name": "",
+                "group_id": 4,
+                "closed": "true",
+                "country_id": self.ANY,
+                "region_id": self.ANY,
+                "pollutant": "",
+                "location_type": self.ANY,
+                "view": "advanced",
+                "action": "results"
+                }
+
+    def group_id(self, group_id):
+        """ Set the monitoring network to search, 4 is the AURN
+
+        Keyword arguments:
+            group_id (int): DEFRA network id
+        """
+        self.parameters["group_id"] = int(group_id)
+        return self
+
+    def include_closed(self, include_closed):
+        """ Set whether closed sites are included
+
+        Keyword arguments:
+            include_closed (bool): False to only search open sites
+        """
+        self.parameters["closed"] = "true" if include_closed else "false"
+        return self
+
+    def country_id(self, country_id=None):
+        """ Set the country to search
+
+        Keyword arguments:
+            country_id (int): DEFRA country id, None for any country
+        """
+        self.parameters["country_id"] = (
+                self.ANY if country_id is None else int(country_id)
+                )
+        return self
+
+    def region_id(self, region_id=None):
+        """ Set the region to search
+
+        Keyword arguments:
+            region_id (int): DEFRA region id, None for any region
+        """
+        self.parameters["region_id"] = (
+                self.ANY if region_id is None else int(region_id)
+                )
+        return self
+
+    def location_type(self, location_type=None):
+        """ Set the environment type to search (e.g urban background)
+
+        Keyword arguments:
+            location_type (int): DEFRA location type id, None for any type
+        """
+        self.parameters["location_type"] = (
+                self.ANY if location_type is None else int(location_type)
+                )
+        return self
+
+    def pollutant(self, pollutant=None):
+        """ Set the pollutant sites must measure
+
+        Keyword arguments:
+            pollutant (int): DEFRA pollutant id, None for any pollutant
+        """
+        self.parameters["pollutant"] = (
+                "" if pollutant is None else int(pollutant)
+                )
+        return self
+
+    def urn(self):
+        """ Generate the path and query string of the search
+
+        Returns:
+            URN to add to "AURN Domain", in the same form as "AURN Metadata
+            Search" in the config
+        """
+        return f"{self.path}?{urlencode(self.parameters)}"
+
+    def __str__(self):
+        return self.urn()
+
+
+class AURNAPI:
+    """ Handles communication with the AURN/DEFRA website to get metadata
+    and measurements
+
+    Attributes:
+        config (dict): Contains config information from config.json
+
+        metadata (list): Contains dictionaries which house all metadata for
+        AURN sites that were active in the specified data range, split in to
+        "tags" for all text info (Site Name etc) and "fields" for location
+        info (Latitude etc)
+
+        measurement_csvs (defaultdict): Contains all measurements downloaded,
+        split by year and then by station. This should be cleared regularly
+        using clear_measurement_csvs to prevent memory issues
+
+        measurement_jsons: Contains all measurement json lists ready to be
+        exported to InfluxDB 2.x instance, split by year then by station.
+        Should be cleared regularly using clear_measurtement_jsons to 
+        prevent memory issues
+
+        measurement_methods (defaultdict): Instrument/method of each
+        measurement column (e.g FIDAS), split by year then by station.
+        Cleared with measurement_csvs
+
+        out_of_range_points (int): Number of measurements with a time
+        outside the requested date range
+
+        boundary_measurements (dict): Pollutant and time of measurements
+        from the last csv exported for each site that fall in the next
+        year, keyed by download code
+
+        boundary_duplicates (int): Number of measurements removed because
+        they were already exported from the previous year's csv
+
+        download_times (defaultdict): UTC time each measurement csv was
+        downloaded, split by year then by station. Cleared with
+        measurement_csvs
+
+        dropped_zeros (int): Number of zero measurements dropped because
+        their pollutant is listed in "Zero As Missing" in the config
+
+        dropped_statuses (int): Number of measurements dropped because
+        their status isn't in "Allowed Statuses" in the config
+
+        xpaths (dict): Compiled XPaths from config, keyed by config key
+
+        missing_sentinels (list): Lower case strings that represent missing
+        measurements. DEFAULT_MISSING_SENTINELS plus any listed in
+        "Missing Data Sentinels" in the config
+
+        dropped_sites (list): Sites removed during get_metadata, as
+        (reason, metadata row) tuples
+
+        rate_limiter (RateLimiter): Limits the rate of all requests sent to
+        the DEFRA website
+
+        request_count (int): Number of requests sent to the DEFRA website
+
+        request_lock (threading.Lock): Stops request_count,
+        failed_requests, rate_limited_count and next_mirror being updated by
+        two threads at once
+
+        failed_requests (int): Number of measurement csvs that couldn't be
+        downloaded
+
+        request_errors (list): AURNRequestError for every measurement csv
+        and site info page that couldn't be downloaded
+
+        domain (str): "AURN Domain" from config, scheme checked and
+        trailing slashes removed
+
+        mirror_domains (list): Domains measurement csvs are downloaded from
+        in turn. "AURN Mirror Domains" from config, or just "AURN Domain"
+        if none are listed
+
+        next_mirror (int): Index of the mirror used for the next download
+
+        request_timeout (float): Seconds to wait for the DEFRA website to
+        connect or send data before a request fails. "Request Timeout" from
+        config, default 60
+
+        session (requests.Session): Session used for all requests to the
+        DEFRA website. Keeps any cookies DEFRA sets on the first visit
+        (consent, session etc) so later site info requests send them back
+
+        challenge_markers (list): Lower case strings that show an HTML
+        response is a rate limit or challenge page.
+        DEFAULT_CHALLENGE_MARKERS plus any listed in "Challenge Markers" in
+        the config
+
+        rate_limit_retries (int): Times to retry a rate limited request,
+        "Rate Limit Retries" from config, default 3
+
+        rate_limit_backoff (float): Seconds to wait before retrying a rate
+        limited request with no Retry-After header, "Rate Limit Backoff"
+        from config, default 60
+
+        rate_limited_count (int): Number of requests that were rate limited
+        or challenged
+
+        metadata_complete (bool): False if get_metadata stopped scraping
+        download codes because its deadline passed
+
+        retry_policy (RetryPolicy): How many times and how long to wait
+        before retrying a request that failed for a transient reason.
+        "Request Retries" and "Request Retry Backoff" from config, default
+        3 and 1
+
+        min_year (int): Earliest year measurements can be downloaded for.
+        "Min Year" from config, default 1970
+
+        max_year (int): Latest year measurements can be downloaded for.
+        "Max Year" from config, default next year
+
+        metadata_query (MetadataQuery): Query used to search for sites
+        instead of "AURN Metadata Search" from config, None to use the
+        config
+
+    Methods:
+        before_request: Wait for the rate limiter and count the request
+
+        get: Send a GET request to the DEFRA website, retrying transient
+        failures and raising AURNRequestError if it fails
+
+        is_retryable: Check if a failed request should be sent again
+
+        read_csv: Download a measurement csv, retrying if the download is
+        interrupted
+
+        send_request: Send a single GET request to the DEFRA website,
+        waiting and retrying if rate limited
+
+        get_metadata: Download a csv file containing info on all AURN sites,
+        use the UK-AIR ID to search the AURN website for the "Download Code"
+        for the site (A 2-4 character code that is used in the download url
+        for the measurement csvs) and put metadata and download code in to a
+        dictionary that gets put in to a list
+
+        drop_site: Record a site removed during get_metadata
+
+        get_metadata_cached: Load metadata from a cache file, downloading
+        it if the cache is missing, out of date or for a different config
+
+        metadata_cache_key: Hash of the metadata config and years used to
+        download metadata
+
+        metadata_to_json: Convert metadata to a json string
+
+        save_metadata: Save metadata to a json file
+
+        load_metadata: Load metadata from a json file instead of
+        downloading it
+
+        select_metadata_columns: Keep only the listed metadata tags and
+        fields for every site
+
+        add_site: Add a site's metadata to metadata
+
+        add_optional_metadata: Add traffic context metadata to a site if
+        it has any
+
+        pollutant_list: Get the pollutants a site measures from its
+        metadata
+
+        sites_by_pollutant_count: Rank sites by number of pollutants
+        measured
+
+        select_sites: Keep only the sites with a listed site code
+
+        filter_by_pollutant: Keep only the sites that measure a pollutant
+
+        filter_by_region: Keep only the sites in one of the listed regions
+
+        filter_by_environment: Keep only the sites with one of the listed
+        environment types
+
+        get_metadata_table: Download table of metadata for all sites, from
+        the metadata csv or a JSON endpoint
+
+        metadata_search_urn: URN used to search for sites
+
+        get_download_code: Scrape the download code for a site from its site
+        info page
+
+        measurement_csv_url: Generate the url of a measurement csv
+
+        verify_site_codes: Test fetch one measurement csv per site to check
+        its download code
+
+        get_csv_measurements: Download measurements from AURN and formats csvs
+        in to nicer format for machine reading
+
+        csv_to_json_list: Converts csvs to list of jsons to be exported to
+        InfluxDB v2.0 instance
+
+        iter_csv_measurements: Download measurement csvs for a list of
+        sites and years, several at once
+
+        download_all: Download measurements for every site and every year
+        in a date range
+
+        zero_as_missing_columns: Finds the measurement columns whose zeros
+        are treated as missing
+
+        count_valid_measurements: Counts the measurements in a csv that
+        should be exported
+
+        remove_boundary_duplicates: Remove measurements already exported
+        from the previous year's csv
+
+        csv_as_text: Returns csv as text file for writing
+
+        csv_save: Saves csv measurements to path
+
+        clear_measurement_csvs: Clear measurement_csvs
+
+        clear_measurement_jsons: Clear measurement_jsons
+
+    """
+    def __init__(self, config, rate_limiter=None, session=None,
+                 metadata_query=None):
+        """Initialises class
+
+        Keyword arguments:
+            config (dict): Contains info used in class, configured in
+            config.json
+
+            rate_limiter (RateLimiter): Shared rate limiter for all requests
+            to the DEFRA website. One is made using "Requests Per Second"
+            from the config if not provided
+
+            session (requests.Session): Session to send requests with, a
+            new one is made if not provided. Anything with a get method
+            and headers dict that behaves like requests.Session can be
+            used, e.g a stub serving saved pages so scraping and parsing
+            can be tested without the live DEFRA website
+
+            metadata_query (MetadataQuery): Query used to search for sites,
+            "AURN Metadata Search" from the config is used if not provided
+
+        Raises:
+            ValueError if any XPath in config is invalid, a domain doesn't
+            use http or https or a required config key is missing
+        """
+        missing_keys = [
+                key for key in REQUIRED_CONFIG_KEYS if key not in config
+                ]
+        if len(missing_keys) > 0:
+            raise ValueError(
+                f"Config is missing required keys: {', '.join(missing_keys)}"
+            )
+        self.config = config
+        self.metadata = list()
+        self.measurement_csvs = defaultdict(dict)
+        self.measurement_jsons = defaultdict(dict)
+        self.measurement_methods = defaultdict(dict)
+        self.download_times = defaultdict(dict)
+        self.dropped_zeros = 0
+        self.dropped_statuses = 0
+        self.out_of_range_points = 0
+        self.boundary_measurements = dict()
+        self.boundary_duplicates = 0
+        pattern_errors = validate_patterns(self.config)
+        if len(pattern_errors) > 0:
+            raise ValueError(
+                "Invalid XPaths in config: " + "; ".join(
+                    f"{key}: {error}" for key, error in pattern_errors
+                    )
+            )
+        self.xpaths = {
+                xpath_key: compile_xpath(self.config[xpath_key])
+                for xpath_key in XPATH_CONFIG_KEYS
+                }
+        self.missing_sentinels = DEFAULT_MISSING_SENTINELS + [
+                str(sentinel).strip().lower() for sentinel in
+                self.config.get("Missing Data Sentinels", [])
+                ]
+        self.dropped_sites = list()
+        self.metadata_complete = True
+        if rate_limiter is None:
+            rate_limiter = RateLimiter(
+                    self.config.get("Requests Per Second", 0)
+                    )
+        self.rate_limiter = rate_limiter
+        self.request_count = 0
+        self.request_lock = threading.Lock()
+        self.failed_requests = 0
+        self.request_errors = list()
+        self.domain = validate_domain(self.config['AURN Domain'])
+        self.mirror_domains = [
+                validate_domain(mirror) for mirror in
+                self.config.get("AURN Mirror Domains", [])
+                ]
+        if len(self.mirror_domains) == 0:
+            self.mirror_domains = [self.domain]
+        self.next_mirror = 0
+        self.request_timeout = self.config.get("Request Timeout", 60)
+        if session is None:
+            session = req.Session()
+        self.session = session
+        self.session.headers.update(request_headers(self.config))
+        self.challenge_markers = DEFAULT_CHALLENGE_MARKERS + [
+                str(marker).strip().lower() for marker in
+                self.config.get("Challenge Markers", [])
+                ]
+        self.rate_limit_retries = int(
+                self.config.get("Rate Limit Retries", 3)
+                )
+        self.rate_limit_backoff = float(
+                self.config.get("Rate Limit Backoff", 60)
+                )
+        self.rate_limited_count = 0
+        self.retry_policy = RetryPolicy(
+                retries=self.config.get("Request Retries", 3),
+                backoff=self.config.get("Request Retry Backoff", 1)
+                )
+        self.min_year = int(self.config.get("Min Year", 1970))
+        self.max_year = int(
+                self.config.get("Max Year", 0) or dt.datetime.now().year + 1
+                )
+        self.metadata_query = metadata_query
+
+    def before_request(self):
+        """ Waits for the rate limiter and counts the request
+
+        Called before every request to the DEFRA website
+        """
+        self.rate_limiter.wait()
+        with self.request_lock:
+            self.request_count += 1
+
+    def get(self, url, stream=False):
+        """ Sends a GET request to the DEFRA website, retrying transient
+        failures
+
+        Requests with no response (timeouts, dropped connections) or a
+        server error (5xx) are sent again according to retry_policy. Rate
+        limits are handled by send_request
+
+        Keyword arguments:
+            url (str): Url to request
+
+            stream (bool): Stream the response body instead of downloading
+            it straight away
+
+        Returns:
+            requests.Response with a successful status code
+
+        Raises:
+            AURNRequestError if the request still failed after all retries,
+            or failed in a way that won't be fixed by retrying (e.g 404)
+        """
+        return self.retry_policy.run(
+                lambda: self.send_request(url, stream),
+                self.is_retryable
+                )
+
+    def is_retryable(self, error):
+        """ Checks if a failed request should be sent again
+
+        Keyword arguments:
+            error (Exception): Error the request raised
+
+        Returns:
+            True if the error is an AURNRequestError that could succeed if
+            sent again. Rate limits are retried by send_request instead
+        """
+        return (
+                isinstance(error, AURNRequestError) and
+                not isinstance(error, AURNRateLimitedError) and
+                error.is_retryable()
+                )
+
+    def read_csv(self, url):
+        """ Downloads a measurement csv and parses it as it downloads
+
+        The body of a streamed response is read after the request
+        succeeds, so a timeout or dropped connection part way through a
+        large csv is raised while parsing. These are retried according to
+        retry_policy, the same as a failed request
+
+        Keyword arguments:
+            url (str): Url of the measurement csv
+
+        Returns:
+            DataFrame of the csv, the 4 line header skipped
+
+        Raises:
+            AURNRequestError if the csv couldn't be downloaded or the
+            download kept being interrupted
+        """
+        def download():
+            csv_response = self.send_request(url, stream=True)
+            csv_response.raw.decode_content = True
+            try:
+                return pd.read_table(csv_response.raw, sep=",", skiprows=4,
+                                     low_memory=False,
+                                     skip_blank_lines=True)
+            except (req.exceptions.RequestException,
+                    urllib3.exceptions.HTTPError, OSError,
+                    pd.errors.ParserError, pd.errors.EmptyDataError) as error:
+                # A csv cut short can also fail to parse
+                raise AURNRequestError(
+                        url, reason=f"Download interrupted ({error})"
+                        ) from error
+            finally:
+                csv_response.close()
+
+        return self.retry_policy.run(download, self.is_retryable)
+
+    def send_request(self, url, stream=False):
+        """ Sends a GET request to the DEFRA website
+
+        If the request is rate limited (429) or answered with a challenge
+        page containing one of challenge_markers, the response is closed and
+        the request is sent again after waiting for the time in the
+        Retry-After header, or "Rate Limit Backoff" seconds if there isn't
+        one. This happens up to "Rate Limit Retries" times
+
+        Keyword arguments:
+            url (str): Url to request
+
+            stream (bool): Stream the response body instead of downloading
+            it straight away
+
+        Variables:
+            rate_limited (bool): Was the response a 429 or challenge page?
+
+        Returns:
+            requests.Response with a successful status code
+
+        Raises:
+            AURNRequestError if there was no response or the status code
+            wasn't successful
+
+            AURNRateLimitedError if the request was still rate limited
+            after all retries
+        """
+        attempt = 0
+        while True:
+            self.before_request()
+            logger.debug("GET %s", url)
+            try:
+                response = self.session.get(
+                        url,
+                        stream=stream,
+                        timeout=self.request_timeout
+                        )
+            except req.exceptions.RequestException as error:
+                raise AURNRequestError(url, reason=str(error)) from error
+            rate_limited = response.status_code == 429
+            if (response.ok and "text/html" in
+                    response.headers.get("Content-Type", "")):
+                page_text = response_content(response).decode(
+                        response.encoding or "utf-8", errors="replace"
+                        ).lower()
+                rate_limited = any(
+                        marker in page_text for marker in
+                        self.challenge_markers
+                        )
+            if not rate_limited:
+                break
+            # The body isn't needed, free the connection before waiting
+            response.close()
+            if attempt >= self.rate_limit_retries:
+                raise AURNRateLimitedError(
+                        url, 429, "Rate limited or challenged by DEFRA"
+                        )
+            wait_time = retry_after_seconds(
+                    response.headers.get("Retry-After"),
+                    self.rate_limit_backoff
+                    )
+            logger.warning("Rate limited requesting %s, retrying in %.0fs",
+                           url, wait_time)
+            time.sleep(wait_time)
+            attempt += 1
+            with self.request_lock:
+                self.rate_limited_count += 1
+        if not response.ok:
+            logger.info("%s returned %s %s", url, response.status_code,
+                        response.reason)
+            raise AURNRequestError(url, response.status_code, response.reason)
+        return response
+
+    def get_metadata(self, start_year, end_year, progress=None,
+                     deadline=None):
+        """ Downloads metadata from AURN/DEFRA website
+
+        As there's no official Python API for the AURN, this function scrapes
+        the AURN/DEFRA website's HTML source for a) a csv containing all
+        metadata for all stations in the network and b) The download link for
+        csv files in the network. If "Metadata Backend" is set to "JSON" in
+        the config, the metadata is read from a JSON endpoint instead and
+        the HTML scraper is only used for sites missing a download code
+
+        Sites whose metadata can't be read (e.g a malformed date or
+        missing coordinates) are skipped and recorded in dropped_sites
+        rather than stopping the export
+
+        If deadline passes while download codes are being scraped, the
+        remaining site info pages aren't requested, those sites are dropped
+        and metadata_complete is set to False
+
+        Keyword arguments:
+            start_year (int): The year the measurement download will start
+            from
+
+            end_year (int): The last year the measurement download will cover
+
+            progress (callable): Called with the number of site info pages
+            scraped so far and the total after each one is scraped, e.g to
+            show a progress bar. Nothing is reported if None
+
+            deadline (float): time.monotonic() value to stop scraping at,
+            no deadline if None
+
+        Variables:
+            metadata_csv (DataFrame): Metadata for all sites in the network,
+            from get_metadata_table
+
+            site_start_date (datetime): The date the site started
+            operating
+
+            site_start_year (int): The year the site started operating
+
+            site_end_year (int): The year the site ended operating.
+            Set to current year + 1 if still in operation (nan in csv) or
+            if the end date is after end_date_horizon
+
+            end_date_horizon (int): End dates after this year are
+            placeholders, current year + "End Date Horizon" from config
+            (default 5)
+
+            drop_not_yet_active (bool): Drop sites with a start date in the
+            future, "Drop Not Yet Active Sites" from config (default True)
+
+            metadata_workers (int): Number of site info pages to request at
+            once, "Metadata Workers" from config (default 1)
+
+            date_formats (list): Formats of the Start Date and End Date
+            columns, "Metadata Date Formats" from config (default
+            DEFAULT_METADATA_DATE_FORMATS)
+
+            site_rows (list): Index, metadata row and download code of the
+            sites to add
+
+            sites_to_scrape (list): Index, metadata row and UK-AIR ID of the
+            sites whose download code needs scraping
+
+            start_year_in_range (bool): Do start_year and end_year fall within
+            site_start_year?
+
+            end_year_in_range (bool): Do start_year and end_year fall within
+            site_end_year?
+
+            data_not_available (bool): Does the date range cross the dates
+            the site was active?
+
+            not_aurn_site (bool): Is the site an AURN site? Some sites in the
+            csv are duplicates with different names but the same download url,
+            one of the duplicates doesn't state it's AURN so this test removes
+            them
+
+            scraped_codes (list): Download codes scraped for
+            sites_to_scrape, None if one couldn't be found
+
+            inactive_sites (int): Number of sites not active in the date
+            range
+
+        Returns:
+            Number of sites removed because they weren't active between
+            start_year and end_year
+
+        Raises:
+            ValueError if end_year is before start_year
+        """
+        # A reversed range would silently match no sites
+        if end_year < start_year:
+            raise ValueError(
+                f"End year {end_year} is before start year {start_year}"
+            )
+
+        metadata_csv = self.get_metadata_table()
+        for column in metadata_csv.columns:
+            metadata_csv[column] = metadata_csv[column].map(decode_entities)
+        end_date_horizon = (dt.datetime.now().year +
+                            self.config.get("End Date Horizon", 5))
+        drop_not_yet_active = self.config.get("Drop Not Yet Active Sites",
+                                              True)
+        now = dt.datetime.now()
+        metadata_workers = max(int(self.config.get("Metadata Workers", 1)), 1)
+        date_formats = self.config.get(
+                "Metadata Date Formats", DEFAULT_METADATA_DATE_FORMATS
+                )
+        site_rows = list()
+        sites_to_scrape = list()
+        inactive_sites = 0
+
+        # Get download code (Usually 3 characters) for all sites
+        # e.g Aberdeen(UKA00399) is ABD
+        for index, row in metadata_csv.iterrows():
+            try:
+                # Check start and end year, move to next if site
+                # wasn't active between them
+                # Also skip if not explicitly stated as AURN
+                if row["Start Date"] != "Unavailable":
+                    site_start_date = parse_metadata_date(
+                            row["Start Date"], "Start Date", date_formats
+                            )
+                    site_start_year = site_start_date.year
+                    # Planned sites can be listed before they start measuring,
+                    # they have no csvs to download yet
+                    if drop_not_yet_active and site_start_date > now:
+                        self.drop_site("Not yet active", row)
+                        continue
+                else:
+                    site_start_year = 1990
+                    # If the start date is unavailable, assume it's early
+                    # Unsure why unavailable appears for only one site at
+                    # the time of commenting but this gets around it and
+                    # any future issues
+                if str(row["End Date"]) != "nan":
+                    site_end_year = parse_metadata_date(
+                            row["End Date"], "End Date", date_formats
+                            ).year
+                else:
+                    site_end_year = dt.datetime.now().year + 1
+                # Some sites have placeholder end dates far in the future
+                # (e.g 2099-12-31), treat these as still open so whether the
+                # site has data is decided by the measurement download
+                if site_end_year > end_date_horizon:
+                    site_end_year = dt.datetime.now().year + 1
+
+                start_year_in_range = (site_start_year <= start_year <=
+                                       site_end_year)
+                end_year_in_range = (site_start_year <= end_year <=
+                                     site_end_year)
+                data_not_available = (not start_year_in_range and not
+                                      end_year_in_range)
+                # Checks if network started after selected period or ended
+                # before
+                not_aurn_site = ("AURN" not in str(row["Networks"]))
+                # Checks if site is actually AURN, some sites double up with
+                # different
+                # names but one of the duplicates doesn't state AURN
+                if data_not_available:
+                    self.drop_site("Not active in date range", row)
+                    inactive_sites += 1
+                    continue
+                if not_aurn_site:
+                    self.drop_site("Not an AURN site", row)
+                    continue
+
+                # Use download code from JSON metadata if present, otherwise
+                # scrape it from the site info page once all sites are
+                # checked
+                try:
+                    site_rows.append(
+                            (index, row, SiteCode(row.get("Download Code")))
+                            )
+                except ValueError:
+                    # A row without a UK-AIR ID would send a request for a
+                    # site info page that can't exist
+                    uk_air_id = str(row.get("UK-AIR ID", "")).strip()
+                    if uk_air_id.lower() in ["", "nan"]:
+                        self.drop_site("No UK-AIR ID", row, logging.WARNING)
+                        continue
+                    sites_to_scrape.append((index, row, uk_air_id))
+            except (KeyError, TypeError, ValueError) as error:
+                # A site with unreadable metadata shouldn't stop the rest
+                # being exported
+                self.drop_site(
+                        f"Metadata could not be read ({error!r})", row,
+                        logging.WARNING
+                        )
+
+        # Scraping download codes is the slowest part of getting the
+        # metadata, the site info pages are requested in parallel if
+        # "Metadata Workers" is more than 1
+        scraped_codes = list()
+        self.metadata_complete = True
+        with ThreadPoolExecutor(max_workers=metadata_workers) as executor:
+            for download_code in executor.map(
+                    lambda site: self.get_download_code(site[2]),
+                    sites_to_scrape
+                    ):
+                scraped_codes.append(download_code)
+                if progress is not None:
+                    progress(len(scraped_codes), len(sites_to_scrape))
+                if deadline is not None and time.monotonic() > deadline:
+                    # Pages already being requested are finished, the rest
+                    # aren't requested
+                    self.metadata_complete = False
+                    executor.shutdown(cancel_futures=True)
+                    break
+        for (index, row, uk_air_id), download_code in zip(sites_to_scrape,
+                                                          scraped_codes):
+            if download_code is None:
+                self.drop_site("No download code found", row,
+                               logging.WARNING)
+            else:
+                site_rows.append((index, row, download_code))
+        for index, row, uk_air_id in sites_to_scrape[len(scraped_codes):]:
+            self.drop_site("Max runtime exceeded before download code was "
+                           "scraped", row, logging.WARNING)
+
+        # Sites are added in the order of the metadata table
+        for index, row, download_code in sorted(site_rows,
+                                                key=lambda site: site[0]):
+            try:
+                self.add_site(row, download_code)
+            except (KeyError, TypeError, ValueError) as error:
+                self.drop_site(
+                        f"Metadata could not be read ({error!r})", row,
+                        logging.WARNING
+                        )
+        if len(self.config.get("Metadata Columns", [])) > 0:
+            self.select_metadata_columns(self.config["Metadata Columns"])
+        logger.info("Found metadata for %d sites", len(self.metadata))
+        return inactive_sites
+
+    def drop_site(self, reason, row, level=logging.INFO):
+        """ Records a site removed during get_metadata in dropped_sites
+
+        Keyword arguments:
+            reason (str): Why the site was removed
+
+            row (Series): Row of the metadata table for the site
+
+            level (int): Logging level to log the site at. Sites removed
+            on purpose (e.g not active in the date range) are logged as
+            info, ones removed because of a problem as warnings. The full
+            metadata row is logged at debug level
+        """
+        self.dropped_sites.append((reason, row))
+        logger.log(level, "Skipped site %s: %s", row.get("Site Name"),
+                   reason)
+        if logger.isEnabledFor(logging.DEBUG):
+            logger.debug("Metadata of skipped site %s:\n%s",
+                         row.get("Site Name"), format_row(row))
+
+    def filter_by_region(self, regions):
+        """ Keep only the sites in metadata in one of the listed regions
+
+        Uses the "Region" tag added from the first of REGION_COLUMNS in the
+        metadata csv, e.g Scotland or Greater London. Regions are compared
+        case insensitively. Sites with no region are removed
+
+        Keyword arguments:
+            regions (list): Names of the regions to keep
+
+        Returns:
+            Number of sites removed
+        """
+        wanted_regions = {str(region).strip().lower() for region in regions}
+        site_count = len(self.metadata)
+        self.metadata = [
+                station for station in self.metadata
+                if str(station["tags"].get("Region", "")).lower() in
+                wanted_regions
+                ]
+        logger.info("Removed %d sites not in %s",
+                    site_count - len(self.metadata), ", ".join(regions))
+        return site_count - len(self.metadata)
+
+    def filter_by_environment(self, environment_types):
+        """ Keep only the sites in metadata with one of the listed
+        environment types
+
+        Uses the "Environment Type" tag, e.g Urban Background or Rural
+        Background. Types are compared case insensitively and a type also
+        matches any environment type it is the start of, so Urban keeps
+        Urban Background, Urban Traffic and Urban Industrial sites
+
+        Keyword arguments:
+            environment_types (list): Environment types to keep
+
+        Returns:
+            Number of sites removed
+        """
+        wanted_types = tuple(
+                str(environment_type).strip().lower() for environment_type
+                in environment_types
+                )
+        site_count = len(self.metadata)
+        self.metadata = [
+                station for station in self.metadata
+                if str(station["tags"].get("Environment Type", ""))
+                .strip().lower().startswith(wanted_types)
+                ]
+        logger.info("Removed %d sites not of type %s",
+                    site_count - len(self.metadata),
+                    ", ".join(environment_types))
+        return site_count - len(self.metadata)
+
+    def get_metadata_cached(self, start_year, end_year, cache_path,
+                            ttl_days=7, progress=None, deadline=None):
+        """ Loads metadata from a cache file, downloading it if needed
+
+        Site metadata rarely changes, but downloading it means requesting
+        the site info page of every site. The metadata is saved to
+        cache_path after it is downloaded and used instead of downloading
+        again until it is older than ttl_days. The cache is only used if it
+        was saved with the same metadata config and years, otherwise (or if
+        it can't be read) the metadata is downloaded again and the cache
+        replaced. dropped_sites is empty when the cache is used. The cache
+        isn't replaced if deadline passed before all the metadata was
+        downloaded
+
+        Keyword arguments:
+            start_year (int): The year the measurement download will start
+            from
+
+            end_year (int): The last year the measurement download will cover
+
+            cache_path (str): Path of the cache json file
+
+            ttl_days (float): Days the cache can be used for after it's
+            saved
+
+            progress (callable): Passed to get_metadata if the metadata is
+            downloaded
+
+            deadline (float): Passed to get_metadata if the metadata is
+            downloaded
+
+        Variables:
+            cache_key (str): Hash of the metadata config and years the
+            metadata was downloaded with
+
+        Returns:
+            True if the cache was used, False if the metadata was downloaded
+        """
+        cache_key = self.metadata_cache_key(start_year, end_year)
+        try:
+            if not metadata_cache_status(cache_path, ttl_days)["stale"]:
+                with open(cache_path, "r") as cache_file:
+                    cache = json.load(cache_file)
+                if cache["key"] == cache_key:
+                    metadata = cache["metadata"]
+                    for station in metadata:
+                        station["tags"]["Download Code"] = SiteCode(
+                                station["tags"]["Download Code"]
+                                )
+                    self.metadata = metadata
+                    return True
+        except (OSError, ValueError, KeyError, TypeError):
+            # Missing or unreadable cache, download the metadata instead
+            pass
+        self.get_metadata(start_year, end_year, progress, deadline)
+        if not self.metadata_complete:
+            return False
+        cache_directory = os.path.dirname(cache_path)
+        if cache_directory != "":
+            os.makedirs(cache_directory, exist_ok=True)
+        with open(cache_path, "w") as cache_file:
+            json.dump(
+                    {"key": cache_key, "metadata": self.metadata},
+                    cache_file, default=str
+                    )
+        return False
+
+    def metadata_to_json(self):
+        """ Converts metadata to a json string
+
+        Useful for inspecting the sites found, comparing them between runs
+        or using them in other tools
+
+        Returns:
+            metadata as an indented json list
+        """
+        return json.dumps(self.metadata, indent=2, default=str)
+
+    def save_metadata(self, path):
+        """ Saves metadata to a json file
+
+        Keyword arguments:
+            path (str): Path of the json file
+        """
+        directory = os.path.dirname(path)
+        if directory != "":
+            os.makedirs(directory, exist_ok=True)
+        with open(path, "w") as metadata_file:
+            metadata_file.write(self.metadata_to_json())
+
+    def load_metadata(self, path):
+        """ Loads metadata from a json file saved by save_metadata
+
+        Replaces get_metadata, so measurements can be downloaded for a
+        saved set of sites without scraping the DEFRA website
+
+        Keyword arguments:
+            path (str): Path of the json file
+
+        Raises:
+            ValueError if the file isn't a list of sites with tags and
+            fields, or a download code isn't valid
+        """
+        with open(path, "r") as metadata_file:
+            metadata = json.load(metadata_file)
+        if not isinstance(metadata, list):
+            raise ValueError(f"{path} does not contain a list of sites")
+        for station in metadata:
+            if (not isinstance(station, dict) or "tags" not in station or
+                    "fields" not in station):
+                raise ValueError(
+                    f"{path} contains a site without tags and fields"
+                )
+            station["tags"]["Download Code"] = SiteCode(
+                    station["tags"]["Download Code"]
+                    )
+        self.metadata = metadata
+
+    def metadata_cache_key(self, start_year, end_year):
+        """ Hash of the metadata config and years used to download metadata
+
+        Only the config keys in METADATA_CACHE_CONFIG_KEYS are hashed, so
+        changing unrelated settings (e.g the export backend) doesn't
+        invalidate the cache and credentials are never hashed
+
+        Keyword arguments:
+            start_year (int): The year the measurement download will start
+            from
+
+            end_year (int): The last year the measurement download will cover
+
+        Returns:
+            sha256 hex digest as str
+        """
+        return hashlib.sha256(
+                json.dumps(
+                    [
+                        {
+                            key: self.config.get(key)
+                            for key in METADATA_CACHE_CONFIG_KEYS
+                        },
+                        start_year, end_year, self.metadata_search_urn()
+                    ],
+                    sort_keys=True, default=str
+                    ).encode("utf-8")
+                ).hexdigest()
+
+    def add_site(self, row, download_code):
+        """ Adds a site's metadata to metadata
+
+        Keyword arguments:
+            row (Series): Row of the metadata table for the site
+
+            download_code (SiteCode): Download code for the site
 
-    bracket_pairs = {
-            "(": ")",
-            "[": "]",
-            "{": "}",
-            "<": ">"
-            }
-    open_brackets = list(bracket_pairs.keys())
+        Variables:
+            zone_tag (str): Name of the tag the site's zone is added as,
+            "Zone Tag" from config (default Zone). Not added if blank
 
-    clean_string = ""
-    unbracketed = True
-    close_bracket = ""
-    for character in string_with_brackets:
-        if character in open_brackets:
-            unbracketed = False
-            close_bracket = bracket_pairs[character]
-        if unbracketed:
-            clean_string = f"{clean_string}{character}"
-        if not unbracketed and character == close_bracket:
-            unbracketed = True
+        Raises:
+            KeyError if a required metadata column is missing
 
-    # Sometimes removing brackets leaves trailing spaces. This removes them.
-    while clean_string[-1] == " ":
-        clean_string = clean_string[:-1]
+            ValueError if a coordinate isn't a number
+        """
+        zone_tag = self.config.get("Zone Tag", "Zone")
+        station = {
+                "tags": {
+                    "UK-AIR ID": row["UK-AIR ID"],
+                    "EU Site ID": row["EU Site ID"],
+                    "EMEP Site ID": row["EMEP Site ID"],
+                    "Site Name": row["Site Name"],
+                    "Environment Type": row["Environment Type"],
+                    "Download Code": download_code
+                },
+                "fields": {
+                    "Latitude": float(row["Latitude"]),
+                    "Longitude": float(row["Longitude"]),
+                    "Northing": float(row["Northing"]),
+                    "Easting": float(row["Easting"]),
+                    "Altitude": float(row["Altitude (m)"])
+                }
+            }
+        self.add_optional_metadata(station, row)
+        station["pollutants"] = self.pollutant_list(row)
+        # Agglomeration/non-agglomeration zone used for UK reporting
+        zone = row.get("Zone")
+        if (zone_tag != "" and zone is not None and
+                str(zone).strip() not in ["", "nan"]):
+            station["tags"][zone_tag] = str(zone).strip()
+        for column in REGION_COLUMNS:
+            region = row.get(column)
+            if region is not None and str(region).strip() not in ["", "nan"]:
+                station["tags"]["Region"] = str(region).strip()
+                break
+        self.metadata.append(station)
+
+    def select_metadata_columns(self, columns):
+        """ Keep only the listed metadata tags and fields for every site
+
+        Tags and fields are kept in the order listed. UK-AIR ID, Download
+        Code and Site Name are always kept as they identify the site
 
-    return clean_string
+        Keyword arguments:
+            columns (list): Names of the metadata tags and fields to keep
 
+        Variables:
+            known_columns (set): Names of every tag and field that could be
+            in the metadata
 
-class AURNAPI:
-    """ Handles communication with the AURN/DEFRA website to get metadata
-    and measurements
+        Raises:
+            ValueError if a column isn't a metadata tag or field
+        """
+        known_columns = (
+                {"UK-AIR ID", "EU Site ID", "EMEP Site ID", "Site Name",
+                 "Environment Type", "Download Code", "Latitude",
+                 "Longitude", "Northing", "Easting", "Altitude", "Region",
+                 self.config.get("Zone Tag", "Zone")} |
+                set(OPTIONAL_METADATA_TAGS.keys()) |
+                set(OPTIONAL_METADATA_FIELDS.keys())
+                )
+        unknown_columns = [
+                column for column in columns if column not in known_columns
+                ]
+        if len(unknown_columns) > 0:
+            raise ValueError(
+                f"Metadata Columns in config contains unknown columns: "
+                f"{', '.join(unknown_columns)}. Valid columns are "
+                f"{', '.join(sorted(known_columns))}"
+            )
+        site_keys = ["UK-AIR ID", "Download Code", "Site Name"]
+        columns = site_keys + [
+                column for column in columns if column not in site_keys
+                ]
+        for station in self.metadata:
+            for metadata_type in ["tags", "fields"]:
+                station[metadata_type] = {
+                        column: station[metadata_type][column]
+                        for column in columns
+                        if column in station[metadata_type]
+                        }
 
-    Attributes:
-        config (dict): Contains config information from config.json
+    def add_optional_metadata(self, station, row):
+        """ Adds traffic context metadata to a site if it has any
 
-        metadata (list): Contains dictionaries which house all metadata for
-        AURN sites that were active in the specified data range, split in to
-        "tags" for all text info (Site Name etc) and "fields" for location
-        info (Latitude etc)
+        Roadside sites can have extra metadata (road type, distance to
+        kerb etc) that most background sites don't. Any listed in
+        OPTIONAL_METADATA_TAGS and OPTIONAL_METADATA_FIELDS that are present
+        and not blank are added to the site's tags and fields
 
-        measurement_csvs (defaultdict): Contains all measurements downloaded,
-        split by year and then by station. This should be cleared regularly
-        using clear_measurement_csvs to prevent memory issues
+        Keyword arguments:
+            station (dict): Site metadata with "tags" and "fields"
 
-        measurement_jsons: Contains all measurement json lists ready to be
-        exported to InfluxDB 2.x instance, split by year then by station.
-        Should be cleared regularly using clear_measurtement_jsons to 
-        prevent memory issues
+            row (Series): Row of the metadata table for the site
+        """
+        for optional_metadata, metadata_type in [
+                (OPTIONAL_METADATA_TAGS, "tags"),
+                (OPTIONAL_METADATA_FIELDS, "fields")
+                ]:
+            for name, columns in optional_metadata.items():
+                for column in columns:
+                    value = row.get(column)
+                    if value is None or str(value).strip() in ["", "nan"]:
+                        continue
+                    if metadata_type == "fields":
+                        try:
+                            value = float(value)
+                        except ValueError:
+                            continue
+                    station[metadata_type][name] = value
+                    break
 
-    Methods:
-        get_metadata: Download a csv file containing info on all AURN sites,
-        use the UK-AIR ID to search the AURN website for the "Download Code"
-        for the site (A 2-4 character code that is used in the download url
-        for the measurement csvs) and put metadata and download code in to a
-        dictionary that gets put in to a list
+    def pollutant_list(self, row):
+        """ Gets the pollutants a site measures from its metadata
 
-        get_csv_measurements: Download measurements from AURN and formats csvs
-        in to nicer format for machine reading
+        Keyword arguments:
+            row (Series): Row of the metadata table for the site
 
-        csv_to_json_list: Converts csvs to list of jsons to be exported to
-        InfluxDB v2.0 instance
+        Returns:
+            List of pollutant names from the first of POLLUTANT_LIST_COLUMNS
+            present in the row, empty if none are
+        """
+        for column in POLLUTANT_LIST_COLUMNS:
+            value = row.get(column)
+            if value is None or str(value).strip() in ["", "nan"]:
+                continue
+            return [
+                    pollutant.strip() for pollutant in
+                    re.split(r"[,;]", str(value))
+                    if pollutant.strip() != ""
+                    ]
+        return list()
 
-        csv_as_text: Returns csv as text file for writing
+    def sites_by_pollutant_count(self):
+        """ Ranks sites by number of pollutants measured
 
-        csv_save: Saves csv measurements to path
+        Useful for finding sites that measure many pollutants for multi
+        pollutant studies. Sites with no pollutant list in their metadata
+        have a count of 0
 
-        clear_measurement_csvs: Clear measurement_csvs
+        Returns:
+            List of (site name, download code, pollutant count) tuples,
+            most pollutants first
+        """
+        return sorted(
+                [
+                    (
+                        station["tags"]["Site Name"],
+                        station["tags"]["Download Code"],
+                        len(station.get("pollutants", list()))
+                    )
+                    for station in self.metadata
+                ],
+                key=lambda site: (-site[2], site[0])
+                )
 
-        clear_measurement_jsons: Clear measurement_jsons
+    def select_sites(self, site_codes):
+        """ Keep only the sites in metadata with a listed site code
 
-    """
-    def __init__(self, config):
-        """Initialises class
+        Site codes are compared case insensitively against both the
+        download code (e.g ABD) and UK-AIR ID (e.g UKA00399)
 
         Keyword arguments:
-            config (dict): Contains info used in class, configured in
-            config.json
-        """
-        self.config = config
-        self.metadata = list()
-        self.measurement_csvs = defaultdict(dict)
-        self.measurement_jsons = defaultdict(dict)
+            site_codes (list): Site codes to keep
 
-    def get_metadata(self, start_year, end_year):
-        """ Downloads metadata from AURN/DEFRA website
+        Variables:
+            wanted_codes (set): Upper case site codes to keep
 
-        As there's no official Python API for the AURN, this function scrapes
-        the AURN/DEFRA website's HTML source for a) a csv containing all
-        metadata for all stations in the network and b) The download link for
-        csv files in the network
+            found_codes (set): Upper case site codes matched to a site
+
+        Returns:
+            List of site codes that didn't match any site
+        """
+        wanted_codes = {str(code).strip().upper() for code in site_codes}
+        found_codes = set()
+        selected_metadata = list()
+        for station in self.metadata:
+            station_codes = {
+                    str(station["tags"]["Download Code"]).upper(),
+                    str(station["tags"]["UK-AIR ID"]).upper()
+                    }
+            if len(station_codes & wanted_codes) > 0:
+                selected_metadata.append(station)
+                found_codes.update(station_codes & wanted_codes)
+        self.metadata = selected_metadata
+        return [
+                code for code in site_codes
+                if str(code).strip().upper() not in found_codes
+                ]
+
+    def filter_by_pollutant(self, pollutant):
+        """ Keep only the sites in metadata that measure a pollutant
+
+        Uses the pollutant list in each site's metadata, so sites can be
+        removed before any measurement csvs are downloaded. Names are
+        compared case insensitively and match if the requested name is
+        part of a listed pollutant, so NO2 matches "Nitrogen dioxide (NO2)".
+        Sites with no pollutant list are removed as they can't be shown to
+        measure it
 
         Keyword arguments:
-            start_year (int): The year the measurement download will start
-            from
+            pollutant (str): Name of the pollutant
 
-            end_year (int): The last year the measurement download will cover
+        Returns:
+            Number of sites removed
+        """
+        wanted_pollutant = str(pollutant).strip().lower()
+        site_count = len(self.metadata)
+        self.metadata = [
+                station for station in self.metadata
+                if any(
+                    wanted_pollutant in str(site_pollutant).lower()
+                    for site_pollutant in station.get("pollutants", list())
+                    )
+                ]
+        logger.info("Removed %d sites not measuring %s",
+                    site_count - len(self.metadata), pollutant)
+        return site_count - len(self.metadata)
+
+    def get_metadata_table(self):
+        """ Downloads the table of metadata for all sites in the network
+
+        By default the AURN/DEFRA website's HTML source is scraped for a
+        link to a csv containing metadata for all sites. If "Metadata
+        Backend" is "JSON" in the config, the url in "AURN Metadata JSON"
+        is downloaded instead. It must return a list of objects with the
+        same keys as the columns in the metadata csv, optionally with a
+        "Download Code" key which saves scraping each site info page. If
+        the JSON can't be downloaded or read, a warning is logged and the
+        HTML is scraped instead. When scraping, sites are searched for with
+        metadata_query if set and "AURN Metadata Search" from the config
+        otherwise.
 
         Variables:
             metadata_search_url (str): The url used to search for info on all
@@ -158,31 +2096,88 @@ class AURNAPI:
             metadata_html_source (html object): lxml searchable
             metadata_html_page
 
+            metadata_csv_links (list): All matches for the XPath to the
+            metadata csv link
+
             metadata_csv_link (str): Link to metadata csv, obtained by
             searching html with an XPath string
 
-            site_start_year (int): The year the site started operating
+        Returns:
+            DataFrame containing metadata for all sites, one row per site
 
-            site_end_year (int): The year the site ended operating.
-            Set to current year + 1 if still in operation (nan in csv)
+        Raises:
+            ValueError if no link to the metadata csv is found, or the link
+            found is empty
 
-            start_year_in_range (bool): Do start_year and end_year fall within
-            site_start_year?
+            AURNRequestError if the metadata page or csv can't be
+            downloaded
+        """
+        if self.config.get("Metadata Backend", "HTML") == "JSON":
+            metadata_json_url = self.config.get("AURN Metadata JSON", "")
+            try:
+                if metadata_json_url == "":
+                    raise ValueError("AURN Metadata JSON is blank in config")
+                metadata_json = self.get(metadata_json_url)
+                return pd.DataFrame(
+                        json.loads(response_content(metadata_json))
+                        )
+            except (AURNRequestError, ValueError) as error:
+                # The HTML pages are still the official source of metadata
+                logger.warning("Could not read metadata JSON from %s (%s), "
+                               "scraping HTML metadata instead",
+                               metadata_json_url, error)
 
-            end_year_in_range (bool): Do start_year and end_year fall within
-            site_end_year?
+        # Get HTML file with search results of all sites, open or closed,
+        # in network
+        metadata_search_url = f"{self.domain}{self.metadata_search_urn()}"
+        metadata_html_page = self.get(metadata_search_url)
+        metadata_html_source = html.fromstring(
+                response_content(metadata_html_page)
+                )
 
-            data_not_available (bool): Does the date range cross the dates
-            the site was active?
+        # Search HTML file for link to csv metadata using xpath
+        metadata_csv_links = self.xpaths["XPath to CSV"](
+                metadata_html_source
+                )
+        if len(metadata_csv_links) == 0:
+            raise ValueError(
+                f"No link to the metadata csv found at {metadata_search_url} "
+                f"using \"{self.config['XPath to CSV']}\". The page layout "
+                f"may have changed, check XPath to CSV in config"
+            )
+        metadata_csv_link = str(metadata_csv_links[0]).strip()
+        if metadata_csv_link == "":
+            raise ValueError(
+                f"Link to the metadata csv found at {metadata_search_url} "
+                f"using \"{self.config['XPath to CSV']}\" has an empty href"
+            )
+
+        # Download metadata csv with the same session and headers as every
+        # other request
+        metadata_csv = self.get(metadata_csv_link)
+        return pd.read_table(
+                io.BytesIO(response_content(metadata_csv)), sep=","
+                )
 
-            not_aurn_site (bool): Is the site an AURN site? Some sites in the
-            csv are duplicates with different names but the same download url,
-            one of the duplicates doesn't state it's AURN so this test removes
-            them
+    def metadata_search_urn(self):
+        """ URN used to search for sites
+
+        Returns:
+            metadata_query as a URN if set, "AURN Metadata Search" from
+            the config otherwise
+        """
+        if self.metadata_query is not None:
+            return self.metadata_query.urn()
+        return self.config["AURN Metadata Search"]
+
+    def get_download_code(self, uk_air_id):
+        """ Scrapes the download code for a site from its site info page
 
+        Keyword arguments:
             uk_air_id (str): The ID code for the station, used to search for
             the download code
 
+        Variables:
             site_info_url (str): The url for the information for a site
 
             site_info_html_page (request): Returned information for site info
@@ -194,112 +2189,103 @@ class AURNAPI:
             site_info_link_xpath (list): List of all links in tbale on
             site_info_html_page
 
-            download_code (str): Download url for measurement csvs
-
+        Returns:
+            SiteCode for the site, None if no valid code could be found
         """
+        # Search HTML of site info
+        site_info_url = (
+                f"{self.domain}"
+                f"{self.config['AURN Site Info']}"
+                f"{uk_air_id}"
+                f"{self.config['AURN Site Info Provider']}"
+                )
+        try:
+            site_info_html_page = self.get(site_info_url)
+        except AURNRequestError as error:
+            self.request_errors.append(error)
+            return None
+        site_info_html_source = html.fromstring(
+                response_content(site_info_html_page)
+                )
 
-        # Get HTML file with search results of all sites, open or closed,
-        # in network
-        metadata_search_url = (f"{self.config['AURN Domain']}"
-                               f"{self.config['AURN Metadata Search']}")
-        metadata_html_page = req.get(
-                metadata_search_url,
-                headers={"User-Agent": self.config['User Agent']}
+        # Get download code from HTML
+        site_info_link_xpath = self.xpaths["XPath to Code"](
+                site_info_html_source
                 )
-        metadata_html_source = html.fromstring(metadata_html_page.content)
+        for site_info_link in site_info_link_xpath:
+            if self.config['AURN Site Code Link'] not in site_info_link:
+                continue
+            try:
+                download_code = SiteCode(site_info_link.split('=')[1])
+                logger.info("Scraped download code %s for %s",
+                            download_code, uk_air_id)
+                return download_code
+            except (IndexError, ValueError):
+                # Link didn't contain a usable code, try the next one
+                continue
+        return None
 
-        # Search HTML file for link to csv metadata using xpath
-        metadata_csv_link = metadata_html_source.xpath(
-                self.config["XPath to CSV"]
-                )[0]
+    def measurement_csv_url(self, download_code, year, domain=None):
+        """ Generates the url of the measurement csv for a site and year
 
-        # Download metadata csv
-        metadata_csv = pd.read_table(metadata_csv_link, sep=",")
+        Keyword arguments:
+            download_code (str): The download code found during the metadata
+            search
 
-        # Get download code (Usually 3 characters) for all sites
-        # e.g Aberdeen(UKA00399) is ABD
-        for index, row in metadata_csv.iterrows():
-            # Check start and end year, move to next if site
-            # wasn't active between them
-            # Also skip if not explicitly stated as AURN
-            if row["Start Date"] != "Unavailable":
-                site_start_year = dt.datetime.strptime(
-                        row["Start Date"],
-                        "%Y-%m-%d"
-                        ).year
-            else:
-                site_start_year = 1990
-                # If the start date is unavailable, assume it's early
-                # Unsure why unavailable appears for only one site at
-                # the time of commenting but this gets around it and
-                # any future issues
-            if str(row["End Date"]) != "nan":
-                site_end_year = dt.datetime.strptime(
-                        row["End Date"],
-                        "%Y-%m-%d"
-                        ).year
-            else:
-                site_end_year = dt.datetime.now().year + 1
-
-            start_year_in_range = (site_start_year <= start_year <=
-                                   site_end_year)
-            end_year_in_range = (site_start_year <= end_year <= site_end_year)
-            data_not_available = (not start_year_in_range and not
-                                  end_year_in_range)
-            # Checks if network started after selected period or ended before
-            not_aurn_site = ("AURN" not in str(row["Networks"]))
-            # Checks if site is actually AURN, some sites double up with
-            # different
-            # names but one of the duplicates doesn't state AURN
-            if data_not_available or not_aurn_site:
-                continue
+            year (str): The year of the measurements, YYYY format
 
-            # Search HTML of site info
-            uk_air_id = row['UK-AIR ID']
-            site_info_url = (
-                    f"{self.config['AURN Domain']}"
-                    f"{self.config['AURN Site Info']}"
-                    f"{uk_air_id}"
-                    f"{self.config['AURN Site Info Provider']}"
-                    )
-            site_info_html_page = req.get(
-                    site_info_url,
-                    headers={"User-Agent": self.config['User Agent']}
-                    )
-            site_info_html_source = html.fromstring(
-                    site_info_html_page.content
-                    )
+            domain (str): Domain to download from, "AURN Domain" from the
+            config if None
 
-            # Get download code from HTML
-            site_info_link_xpath = site_info_html_source.xpath(
-                    self.config["XPath to Code"]
-                    )
-            for site_info_link in site_info_link_xpath:
-                download_code = None
-                if self.config['AURN Site Code Link'] in site_info_link:
-                    download_code = site_info_link.split('=')[1]
-                    continue
-            if download_code is not None:
-                self.metadata.append(
-                        {
-                            "tags": {
-                                "UK-AIR ID": row["UK-AIR ID"],
-                                "EU Site ID": row["EU Site ID"],
-                                "EMEP Site ID": row["EMEP Site ID"],
-                                "Site Name": row["Site Name"],
-                                "Environment Type": row["Environment Type"],
-                                "Zone": row["Zone"],
-                                "Download Code": download_code
-                            },
-                            "fields": {
-                                "Latitude": float(row["Latitude"]),
-                                "Longitude": float(row["Longitude"]),
-                                "Northing": float(row["Northing"]),
-                                "Easting": float(row["Easting"]),
-                                "Altitude": float(row["Altitude (m)"])
-                            }
-                        }
-                    )
+        Returns:
+            Url of the measurement csv
+
+        Raises:
+            ValueError if download_code is not a valid SiteCode or year is
+            outside min_year to max_year
+        """
+        if domain is None:
+            domain = self.domain
+        year = validate_year(year, self.min_year, self.max_year)
+        return (
+                f"{domain.rstrip('/')}/"
+                f"{self.config['AURN Data Link'].strip('/')}/"
+                f"{SiteCode(download_code)}_{year}.csv"
+                )
+
+    def verify_site_codes(self, year):
+        """ Test fetch one measurement csv per site to check its download
+        code
+
+        A wrong download code (e.g from a change to the site info pages
+        breaking the scrape) only shows up as 404s part way through an
+        export. Requesting the csv for one year per site finds these before
+        the export starts. Only the response headers are downloaded, the
+        csv itself isn't read. Requests go through the rate limiter
+
+        Keyword arguments:
+            year (int): Year to test fetch, ideally the most recent year the
+            sites should have measurements for
+
+        Returns:
+            List of (site name, download code, AURNRequestError) tuples for
+            every site whose csv couldn't be downloaded. Sites that closed
+            before the year will be listed too
+        """
+        failed_sites = list()
+        for station in self.metadata:
+            download_code = station["tags"]["Download Code"]
+            try:
+                response = self.get(
+                        self.measurement_csv_url(download_code, year),
+                        stream=True
+                        )
+                response.close()
+            except AURNRequestError as error:
+                failed_sites.append(
+                        (station["tags"]["Site Name"], download_code, error)
+                        )
+        return failed_sites
 
     def get_csv_measurements(self, download_code, year):
         """ Download csvs from AURN website, remove unwanted pollutants and
@@ -309,8 +2295,9 @@ class AURNAPI:
         a preformatted csv. The first stage of this method downloads the csv
         from the data link, appending {Download Code}_{Year}.csv to the end
         to get the measurements for that site for that year in csv format.
-        If no data can be found, None is recorded instead of a dataframe
-        and the method quits early.
+        The csv is streamed in to pandas as it downloads so large files
+        aren't held in memory twice. If no data can be found, None is
+        recorded instead of a dataframe and the method quits early.
 
         The second stage of the method involves making the data look nicer.
         Some of the measurement columns include brackets in their names
@@ -352,6 +2339,11 @@ class AURNAPI:
             year (str): The year you want to download data for, YYYY format
 
         Variables:
+            domain (str): Mirror domain the csv is downloaded from
+
+            request_error (AURNRequestError): Why the last attempt to
+            download the csv failed, None if it didn't
+
             csv_url (str): The url to the formatted csv provided by the AURN
 
             raw_csv (DataFrame): Formatted csv obtained from AURN website
@@ -395,24 +2387,56 @@ class AURNAPI:
             measurement_csv (DataFrame): The new, formatted csv
 
         Returns:
-            Returns none if no csv can be found but this is only to exit the
-            method quickly without nesting a bunch of if statements and
-            making the code look messy
+            The formatted csv as a DataFrame, also stored in
+            measurement_csvs. None if the site has no csv for the year or
+            the csv couldn't be read, as many sites have gaps
         """
         # Generate url to measurement csv and download
-        csv_url = (
-                f"{self.config['AURN Domain']}/"
-                f"{self.config['AURN Data Link']}/"
-                f"{download_code}_{year}.csv"
-                )
-        try:
-            raw_csv = pd.read_table(csv_url, sep=",", skiprows=4,
-                                    low_memory=False)
-        except urllib.error.HTTPError:
+        # Mirrors are used in turn, if one can't be reached the next is
+        # tried. A missing csv (404) won't be on any mirror so isn't retried
+        raw_csv = None
+        request_error = None
+        for mirror_offset in range(0, len(self.mirror_domains)):
+            domain = self.mirror_domains[
+                    (self.next_mirror + mirror_offset) %
+                    len(self.mirror_domains)
+                    ]
+            csv_url = self.measurement_csv_url(download_code, year, domain)
+            # Measurement csvs can be large, parse them as they download
+            # instead of holding the whole file in memory first
+            try:
+                raw_csv = self.read_csv(csv_url)
+            except AURNRequestError as error:
+                request_error = error
+                if error.is_retryable():
+                    continue
+                break
+            break
+        with self.request_lock:
+            self.next_mirror = (
+                    (self.next_mirror + 1) % len(self.mirror_domains)
+                    )
+        if raw_csv is None:
             # If data can't be found, quit out and move on
+            with self.request_lock:
+                self.failed_requests += 1
+            if request_error is not None:
+                self.request_errors.append(request_error)
+            logger.info("No measurement csv for %s in %s", download_code,
+                        year)
             self.measurement_csvs[year][download_code] = None
             return None
 
+        self.download_times[year][download_code] = dt.datetime.now(
+                dt.timezone.utc
+                )
+
+        # Some csvs have CRLF line endings or blank lines at the end, which
+        # leave stray carriage returns in column names and empty rows
+        raw_csv.columns = [str(column).strip() for column in raw_csv.columns]
+        raw_csv = raw_csv.dropna(how="all")
+        raw_csv = raw_csv.dropna(subset=["Date", "time"])
+
         # Remove brackets from columns
         # Some csv files have brackets in their pollutant names (e.g
         # PM<sub>10</sub>) while some don't. Removing the brackets
@@ -425,13 +2449,27 @@ class AURNAPI:
         if len(debracketed_columns.keys()) > 0:
             raw_csv = raw_csv.rename(columns=debracketed_columns)
 
+        # Removing brackets can leave two columns with the same name (e.g
+        # the same pollutant measured by two instruments), suffix repeats
+        # so neither is lost
+        raw_csv.columns = deduplicate_columns(list(raw_csv.columns))
+
+        # Keep the instrument/method in round brackets (e.g FIDAS) that was
+        # removed with the brackets, keyed by the new column name
+        measurement_methods = dict()
+        for raw_column, column in zip(raw_columns_list, raw_csv.columns):
+            method = instrument_method(raw_column)
+            if method is not None:
+                measurement_methods[column] = method
+        self.measurement_methods[year][download_code] = measurement_methods
+
         # Get valid columns
         raw_columns_list = list(raw_csv.columns)
         pollutants_to_remove = list()
         allowed_pollutants = self.config["Pollutants"]
         for column in raw_columns_list:
             pollutant_not_allowed = (
-                column not in allowed_pollutants
+                re.sub(r" \d+$", "", column) not in allowed_pollutants
                 and len(allowed_pollutants) != 0
                 and not any(tag in column for tag in [
                         "unit", "status", "Date", "time"
@@ -453,7 +2491,9 @@ class AURNAPI:
         if len(columns_to_drop) > 0:
             raw_csv = raw_csv.drop(columns_to_drop, axis=1)
 
-        # Turn two date and time columns to datetime
+        # Turn two date and time columns to datetime. AURN times are GMT,
+        # they are kept without a timezone and every writer treats them as
+        # UTC, never the machine's local time
         dt_col = pd.to_datetime(raw_csv.pop('Date'), format='%d-%m-%Y') + \
             pd.to_timedelta(raw_csv.pop('time') + ':00')
         dt_col = dt_col.rename('Datetime')
@@ -480,8 +2520,14 @@ class AURNAPI:
             self.measurement_csvs[year][download_code] = (
                         measurement_csv
                     )
-
-    def csv_to_json_list(self, metadata, download_code, year):
+            return measurement_csv
+        # Columns don't come in measurement, status, unit groups so the
+        # csv can't be read, treat it the same as a missing csv
+        self.measurement_csvs[year][download_code] = None
+        return None
+
+    def csv_to_json_list(self, metadata, download_code, year,
+                         start_date=None, end_date=None):
         """ Converts the formatted csv in to a list of jsons which can be
         exported to an InfluxDB 2.x database.
         
@@ -498,6 +2544,13 @@ class AURNAPI:
 
             year (str): The year the measurements were made
 
+            start_date (datetime): Measurements before this are out of
+            range. Needed when the date range starts partway through a
+            year, also catches misdated rows. None to keep all
+
+            end_date (datetime): Measurements after this are out of range.
+            None to keep all
+
         Variables:
             csv_file (DataFrame): The csv to be converted to json list
 
@@ -509,7 +2562,59 @@ class AURNAPI:
 
             measurement_container (dict): Dict in the format that InfluxDB
             recognises for data export
+
+            zero_columns (set): Measurement columns where a measurement of
+            0 is implausible and is treated as missing data, from
+            zero_as_missing_columns
+
+            allowed_statuses (list): Statuses of measurements to export,
+            e.g ["V"] for ratified only. All are exported if empty
+
+            add_local_time (bool): Add the measurement time in UK local time
+            (GMT/BST) as a "local_time" string field
+
+            add_provenance (bool): Add the year of the csv the measurement
+            came from ("source_year") and when it was downloaded
+            ("scraped_at") as fields
+
+            drop_out_of_range (bool): Drop measurements outside start_date
+            and end_date. They are counted in out_of_range_points either way
+
+            method_tag_name (str): Name appended to the pollutant for the
+            instrument/method tag, blank to not add the tag
+
+            measurement_methods (dict): Instrument/method for each
+            measurement column, only present for columns that had one
+
+            unit_placement (str): Where each pollutant's unit goes, "Tag"
+            (default), "Field" as a string field or "Omit" to leave it out.
+            "Unit Placement" from config
+
+            field_names (dict): Names to export pollutants as, keyed by the
+            pollutant name in the csv. "Pollutant Field Names" from config
+
+            renamed_columns (dict): Field and tag names of each renamed
+            pollutant (measurement, status, unit and method), keyed by the
+            name from the csv
+
+        Raises:
+            ValueError if "Unit Placement" isn't Tag, Field or Omit
         """
+        unit_placement = self.config.get("Unit Placement", "Tag")
+        if unit_placement not in ["Tag", "Field", "Omit"]:
+            raise ValueError(
+                f'"{unit_placement}" is not a valid unit placement, use Tag, '
+                f"Field or Omit"
+            )
+        allowed_statuses = self.config.get("Allowed Statuses", [])
+        add_local_time = self.config.get("Local Time Field", False)
+        method_tag_name = self.config.get("Method Tag", "method")
+        drop_out_of_range = self.config.get("Drop Out Of Range Points", True)
+        add_provenance = self.config.get("Provenance Fields", False)
+        download_time = self.download_times[year].get(download_code)
+        measurement_methods = self.measurement_methods[year].get(
+                download_code, dict()
+                )
         csv_file = self.measurement_csvs[year][download_code]
         column_name_list = list(csv_file.columns)
         status_columns = list()
@@ -520,25 +2625,105 @@ class AURNAPI:
                 status_columns.append(column)
             elif "Datetime" not in column:
                 measurement_columns.append(column)
+        # Pollutants can be exported with shorter, stable names (e.g no2),
+        # along with their status, unit and method tags. Duplicate columns
+        # keep their number (e.g Ozone 2 becomes o3 2)
+        field_names = self.config.get("Pollutant Field Names", dict())
+        renamed_columns = dict()
+        for m_column in measurement_columns:
+            pollutant = re.sub(r" \d+$", "", m_column)
+            if pollutant not in field_names:
+                continue
+            field_name = f"{field_names[pollutant]}{m_column[len(pollutant):]}"
+            renamed_columns[m_column] = field_name
+            for suffix in ["status", "unit", method_tag_name]:
+                renamed_columns[f"{m_column} {suffix}"] = (
+                        f"{field_name} {suffix}"
+                        )
+        zero_columns = self.zero_as_missing_columns(measurement_columns)
         for index, row in csv_file.iterrows():
             measurement_container = {'tags': {}, 'fields': {}}
             measurement_container["time"] = row["Datetime"].to_pydatetime()
+            # Timestamps are the end of the hour measured, so midnight at
+            # the end of the range is still in it
+            out_of_range = (
+                    (start_date is not None and
+                     measurement_container["time"] < start_date) or
+                    (end_date is not None and
+                     measurement_container["time"] > end_date)
+                    )
+            if out_of_range:
+                self.out_of_range_points += 1
+                if drop_out_of_range:
+                    continue
+            if add_provenance:
+                measurement_container["fields"]["source_year"] = int(year)
+                if download_time is not None:
+                    measurement_container["fields"]["scraped_at"] = (
+                            download_time.isoformat(timespec="seconds")
+                            )
+            if add_local_time:
+                measurement_container["fields"]["local_time"] = (
+                        measurement_container["time"]
+                        .replace(tzinfo=dt.timezone.utc)
+                        .astimezone(ZoneInfo("Europe/London"))
+                        .strftime("%Y-%m-%d %H:%M")
+                        )
             measurement_container["measurement"] = (
                     "Automatic Urban Rural Network"
                     )
             for m_column in measurement_columns:
+                if (str(row[m_column]).strip().lower() in
+                        self.missing_sentinels):
+                    continue
+                # Values that aren't numbers or a known sentinel (e.g a new
+                # DEFRA marker) are skipped, the same as in
+                # count_valid_measurements, instead of stopping the export
                 try:
                     measure = float(row[m_column])
-                    if measure != measure:
-                        continue
-                    measurement_container["fields"][m_column] = measure
-                except TypeError:
+                except (TypeError, ValueError):
+                    logger.debug("Skipped unreadable measurement %r in %s "
+                                 "for %s in %s", row[m_column], m_column,
+                                 download_code, year)
+                    continue
+                if measure != measure:
+                    continue
+                if measure == 0 and m_column in zero_columns:
+                    self.dropped_zeros += 1
+                    continue
+                if not status_allowed(row.get(f"{m_column} status"),
+                                      allowed_statuses):
+                    self.dropped_statuses += 1
                     continue
+                measurement_container["fields"][m_column] = measure
             for s_column in status_columns:
-                status = row[s_column]
+                # Blank status and unit cells are read by pandas as NaN,
+                # which would otherwise be exported as the string "nan"
+                if pd.isna(row[s_column]):
+                    continue
+                status = str(row[s_column]).strip()
                 if status == "":
                     continue
-                measurement_container["tags"][s_column] = row[s_column]
+                # Units add a tag per pollutant, which some users would
+                # rather not have in the series key
+                if s_column.endswith(" unit") and unit_placement != "Tag":
+                    if unit_placement == "Field":
+                        measurement_container["fields"][s_column] = status
+                    continue
+                measurement_container["tags"][s_column] = status
+            if method_tag_name != "":
+                for m_column in measurement_columns:
+                    if (m_column in measurement_container["fields"] and
+                            m_column in measurement_methods):
+                        measurement_container["tags"][
+                                f"{m_column} {method_tag_name}"
+                                ] = measurement_methods[m_column]
+            if len(renamed_columns) > 0:
+                for part in ["tags", "fields"]:
+                    measurement_container[part] = {
+                            renamed_columns.get(key, key): value for
+                            key, value in measurement_container[part].items()
+                            }
             for key, value in metadata['tags'].items():
                 measurement_container['tags'][key] = value
             for key, value in metadata['fields'].items():
@@ -549,6 +2734,266 @@ class AURNAPI:
 
         self.measurement_jsons[year][download_code] = container_list
 
+    def iter_csv_measurements(self, site_years, workers=1):
+        """ Downloads measurement csvs for a list of sites and years,
+        several at once
+
+        Downloading csvs one at a time is the slowest part of an export.
+        While one csv is being exported, up to workers - 1 more are
+        downloaded with get_csv_measurements, so requests still go through
+        the shared rate limiter and are retried as usual. No more than
+        workers csvs are downloaded ahead of the one being exported, so
+        memory use doesn't grow with the number of sites and years. Each
+        csv should be removed with clear_measurement_csvs(download_code,
+        year) once exported, as clearing everything would also remove csvs
+        downloaded ahead
+
+        Keyword arguments:
+            site_years (iterable): (download code, year) tuples to download,
+            in the order they will be exported
+
+            workers (int): Maximum number of csvs downloaded at once, 1
+            downloads each csv only when the previous one is exported
+
+        Variables:
+            downloads (deque): Download code, year and future of each csv
+            being downloaded, in the order given
+
+        Yields:
+            (download code, year, csv) tuples in the order given. csv is
+            None if the site has no csv for the year, as returned by
+            get_csv_measurements
+        """
+        workers = max(int(workers), 1)
+        site_years = iter(site_years)
+        downloads = deque()
+        with ThreadPoolExecutor(max_workers=workers) as executor:
+
+            def download_next():
+                site_year = next(site_years, None)
+                if site_year is None:
+                    return
+                downloads.append((
+                    *site_year,
+                    executor.submit(self.get_csv_measurements, *site_year)
+                    ))
+
+            for worker in range(0, workers - 1):
+                download_next()
+            while True:
+                download_next()
+                if len(downloads) == 0:
+                    return
+                download_code, year, download = downloads.popleft()
+                yield download_code, year, download.result()
+
+    def download_all(self, start_date, end_date):
+        """ Downloads measurements for every site in metadata and every
+        year in a date range
+
+        Each year is downloaded with get_csv_measurements and converted
+        with csv_to_json_list, so requests go through the rate limiter and
+        are retried as usual. measurement_csvs and measurement_jsons are
+        cleared after each year, but every container is returned so this
+        is best suited to short date ranges or a few sites
+
+        Keyword arguments:
+            start_date (datetime): Measurements before this aren't included
+
+            end_date (datetime): Measurements after this aren't included
+
+        Returns:
+            List of (download code, year, list of containers) tuples. Years
+            with no csv or no measurements for a site are skipped
+        """
+        measurements = list()
+        for station in self.metadata:
+            download_code = station["tags"]["Download Code"]
+            for year in range(start_date.year, end_date.year + 1):
+                if self.get_csv_measurements(download_code, year) is None:
+                    self.clear_measurement_csvs()
+                    continue
+                self.csv_to_json_list(station, download_code, year,
+                                      start_date, end_date)
+                containers = self.measurement_jsons[year][download_code]
+                self.clear_measurement_csvs()
+                self.clear_measurement_jsons()
+                if len(containers) > 0:
+                    measurements.append((download_code, year, containers))
+        return measurements
+
+    def zero_as_missing_columns(self, columns):
+        """ Finds the measurement columns whose zeros are treated as missing
+
+        Pollutants in "Zero As Missing" can be given by their csv name or
+        the name they're exported as ("Pollutant Field Names"). Brackets
+        are removed from them as they are from the csv columns. A
+        pollutant also covers its duplicate columns (e.g Ozone covers
+        Ozone 2), a duplicate can be listed to only cover that column
+
+        Keyword arguments:
+            columns (list): Column names of a measurement csv
+
+        Variables:
+            listed (set): Pollutants in "Zero As Missing" with brackets
+            removed
+
+            field_names (dict): Names to export pollutants as, keyed by the
+            pollutant name in the csv
+
+        Returns:
+            set of the columns whose zeros are dropped
+        """
+        listed = {
+                remove_brackets(str(pollutant).strip())
+                for pollutant in self.config.get("Zero As Missing", [])
+                if str(pollutant).strip() != ""
+                }
+        field_names = self.config.get("Pollutant Field Names", dict())
+        zero_columns = set()
+        for column in columns:
+            pollutant = re.sub(r" \d+$", "", column)
+            names = {column, pollutant}
+            if pollutant in field_names:
+                names.add(str(field_names[pollutant]))
+                names.add(
+                        f"{field_names[pollutant]}{column[len(pollutant):]}"
+                        )
+            if len(names & listed) > 0:
+                zero_columns.add(column)
+        return zero_columns
+
+    def count_valid_measurements(self, download_code, year, start_date=None,
+                                 end_date=None):
+        """ Counts the measurements in a csv that should be exported
+
+        Counted straight from the csv, separately to csv_to_json_list, so
+        the two can be compared to catch measurements lost during the
+        export. Missing values aren't counted, nor are measurements dropped
+        on purpose (outside the date range, zeros listed in "Zero As
+        Missing" or statuses not in "Allowed Statuses")
+
+        Keyword arguments:
+            download_code (str): The download code for the site, used to find
+            csv in measurement_csvs
+
+            year (str): The year the measurements were made
+
+            start_date (datetime): Measurements before this are out of
+            range. None to count all
+
+            end_date (datetime): Measurements after this are out of range.
+            None to count all
+
+        Variables:
+            in_range (Series): Is each row within the date range?
+
+            values (Series): Measurements in a column, missing values are
+            NaN
+
+        Returns:
+            Number of measurements as int
+        """
+        csv_file = self.measurement_csvs[year].get(download_code)
+        if csv_file is None:
+            return 0
+        zero_columns = self.zero_as_missing_columns(list(csv_file.columns))
+        allowed_statuses = self.config.get("Allowed Statuses", [])
+        in_range = pd.Series(True, index=csv_file.index)
+        if self.config.get("Drop Out Of Range Points", True):
+            times = csv_file["Datetime"]
+            if start_date is not None:
+                in_range &= times >= start_date
+            if end_date is not None:
+                in_range &= times <= end_date
+        count = 0
+        for column in csv_file.columns:
+            if (any(tag in column for tag in ["status", "unit"]) or
+                    "Datetime" in column):
+                continue
+            raw_values = csv_file[column].astype(str).str.strip().str.lower()
+            values = pd.to_numeric(
+                    csv_file[column].where(
+                        ~raw_values.isin(self.missing_sentinels)
+                        ),
+                    errors="coerce"
+                    )
+            valid = values.notna() & in_range
+            if column in zero_columns:
+                valid &= values != 0
+            if (len(allowed_statuses) > 0 and
+                    f"{column} status" in csv_file.columns):
+                valid &= csv_file[f"{column} status"].map(
+                        lambda status: status_allowed(
+                            status, allowed_statuses
+                            )
+                        )
+            count += int(valid.sum())
+        return count
+
+    def remove_boundary_duplicates(self, download_code, year):
+        """ Remove measurements already exported from the previous year's
+        csv
+
+        The last measurement in a year's csv is timestamped midnight on
+        1st January of the next year. Depending on how DEFRA split the
+        files, the next year's csv can start with the same hour, which
+        would be exported twice. Measurements from the previous csv for
+        the site with the same pollutant and time are removed from
+        measurement_jsons. A container is only dropped if it is left without
+        a measurement once its duplicates are removed, containers with no
+        duplicates are kept as they are. Years must be exported in order for
+        this to work
+
+        Keyword arguments:
+            download_code (str): The download code for the site, used to find
+            containers in measurement_jsons
+
+            year (str): The year the measurements were made
+
+        Variables:
+            previous_boundary (set): Pollutant and time of measurements in
+            the previous csv that fall in this year
+
+            next_boundary (set): Pollutant and time of measurements in this
+            csv that fall in the next year
+
+            year_end (datetime): Start of the next year
+
+            removed_duplicate (bool): Were any of a container's measurements
+            removed as duplicates?
+        """
+        previous_boundary = self.boundary_measurements.get(
+                download_code, set()
+                )
+        next_boundary = set()
+        year_end = dt.datetime(int(year) + 1, 1, 1)
+        filtered_containers = list()
+        for container in self.measurement_jsons[year][download_code]:
+            fields = dict()
+            has_measurement = False
+            removed_duplicate = False
+            for field, value in container["fields"].items():
+                measurement = is_measurement(field, value)
+                if (measurement and
+                        (field, container["time"]) in previous_boundary):
+                    self.boundary_duplicates += 1
+                    removed_duplicate = True
+                    continue
+                if measurement:
+                    has_measurement = True
+                    if container["time"] >= year_end:
+                        next_boundary.add((field, container["time"]))
+                fields[field] = value
+            if not removed_duplicate:
+                filtered_containers.append(container)
+            elif has_measurement:
+                container = container.copy()
+                container["fields"] = fields
+                filtered_containers.append(container)
+        self.boundary_measurements[download_code] = next_boundary
+        self.measurement_jsons[year][download_code] = filtered_containers
+
     def csv_as_text(self, download_code, year):
         """ Return dataframe as text
 
@@ -582,10 +3027,26 @@ class AURNAPI:
                     path_or_buf=path
                     )
 
-    def clear_measurement_csvs(self):
-        """ Clear measurement_csvs to reduce memory usage
+    def clear_measurement_csvs(self, download_code=None, year=None):
+        """ Clear measurement_csvs, measurement_methods and download_times
+        to reduce memory usage
+
+        Keyword arguments:
+            download_code (str): Only clear the csv for this site, used
+            with year. Everything is cleared if None
+
+            year (int): Only clear the csv for this year, used with
+            download_code
         """
-        self.measurement_csvs = defaultdict(dict)
+        if download_code is None or year is None:
+            self.measurement_csvs = defaultdict(dict)
+            self.measurement_methods = defaultdict(dict)
+            self.download_times = defaultdict(dict)
+            return
+        for measurements in [self.measurement_csvs,
+                             self.measurement_methods,
+                             self.download_times]:
+            measurements.get(year, dict()).pop(download_code, None)
 
     def clear_measurement_jsons(self):
         """ Clear measurement_jsons to reduce memory usage
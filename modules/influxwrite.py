@@ -5,10 +5,17 @@ Communicates with InfluxDB 2.0 instance, location specified by config
 file and writes data to it synchronously. It accepts data in varying
 formats (TBD, currently only accepts list of jsons)
 
+InfluxDB 1.8 databases can also be written to using its 2.0 compatible
+API. The username and password are sent as the token and the database and
+retention policy as the bucket, with no organisation
+
     Classes:
         InfluxWriter: Handles connection to InfluxDB 2.0 database and
         writes data to it
 
+    Functions:
+        is_transient_write_error: Checks if a write error is worth retrying
+
 """
 
 __author__ = "Idris Hayward"
@@ -22,6 +29,28 @@ __status__ = "Stable Release"
 
 from influxdb_client import InfluxDBClient, WriteOptions
 from influxdb_client.client.write_api import SYNCHRONOUS
+from influxdb_client.rest import ApiException
+from urllib3.exceptions import HTTPError as Urllib3HTTPError
+
+from modules.retry import RetryPolicy
+
+
+def is_transient_write_error(error):
+    """ Checks if a write error is worth retrying
+
+    Server errors (5xx), timeouts and dropped connections are retried.
+    Client errors (4xx) mean the data or credentials are bad, sending it
+    again won't help so they aren't retried.
+
+    Keyword arguments:
+        error (Exception): Error raised when writing
+
+    Returns:
+        True if the write should be retried
+    """
+    if isinstance(error, ApiException):
+        return error.status is None or error.status >= 500
+    return isinstance(error, (Urllib3HTTPError, ConnectionError, TimeoutError))
 
 class InfluxWriter:
     """ Handles connection to InfluxDB 2.0 database and writes data to
@@ -37,15 +66,45 @@ class InfluxWriter:
         config (dict): Contains all information needed to connect and
         write data to InfluxDB 2.0 database
 
+        url (str): Url of the database, "Influx URL" from config or made
+        from "Influx IP" and "Influx Port"
+
         client (InfluxDBClient): Instance of an InfluxDBClient which
         handles the connection to the database
 
         write_client (InfluxDBClient): Subinstance of client, handles
         writing daya to the database
 
+        retry_policy (RetryPolicy): How many times and how long to wait
+        before retrying a write that failed for a transient reason
+
+        batch_size (int): Number of containers sent in each write request,
+        "Influx Batch Size" from config (default 5000). 0 or less sends
+        each list of containers in one request
+
+        buffer (list): Containers waiting to be written as part of a full
+        batch
+
+        write_count (int): Number of write requests sent
+
+        version (int): Version of InfluxDB written to, 1 or 2. "Influx
+        Version" from config (default 2)
+
+        bucket (str): Bucket written to. For version 1 this is the
+        database and retention policy, e.g aurn/autogen
+
+        organisation (str): Organisation written to, "-" for version 1
+
     Methods:
+        is_available: Checks the InfluxDB 2.0 database can be reached
+
         write_container_list (list): Writes list of containers to an
         InfluxDB 2.0 database
+
+        write_batch: Sends one batch of containers in a single write
+        request
+
+        flush: Writes any containers left in the buffer
     """
     def __init__(self, influx_config):
         """ Initialises class and connects to InfluxDB 2.0 database
@@ -64,15 +123,81 @@ class InfluxWriter:
                                       connection
                     - "Influx Organisation": The organisation the
                                              user belongs to
-
-
+                The following keys are optional:
+                    - "Influx Write Retries": Times to retry a failed
+                                              write, default 3
+                    - "Influx Retry Backoff": Seconds to wait before
+                                              first retry, default 1
+                    - "Influx Batch Size": Containers per write
+                                           request, default 5000
+                    - "Influx URL": Full url of the database (e.g
+                                    https://influx.example.com:8086),
+                                    used instead of "Influx IP" and
+                                    "Influx Port" if not blank
+                    - "Influx Version": 2 (default) or 1. Version 1
+                                        uses the following keys instead
+                                        of bucket, token and
+                                        organisation:
+                    - "Influx Database": Database to write to
+                    - "Influx Retention Policy": Retention policy,
+                                                 blank for the default
+                    - "Influx Username": Username, blank if
+                                         authentication is off
+                    - "Influx Password": Password
+
+            Raises:
+                ValueError if "Influx Version" isn't 1 or 2
         """
         self.config = influx_config
-        self.client = InfluxDBClient(url=f'http://{self.config["Influx IP"]}' \
-            f':{self.config["Influx Port"]}', token=self.config["Influx Token"],
-            org=self.config["Influx Organisation"],
+        self.url = self.config.get("Influx URL", "")
+        if self.url == "":
+            self.url = (f'http://{self.config["Influx IP"]}'
+                        f':{self.config["Influx Port"]}')
+        self.version = int(self.config.get("Influx Version", 2))
+        if self.version == 1:
+            # InfluxDB 1.8 accepts v2 writes to database/retention policy
+            # with username:password as the token
+            token = (f'{self.config.get("Influx Username", "")}:'
+                     f'{self.config.get("Influx Password", "")}')
+            self.bucket = (
+                    f'{self.config["Influx Database"]}/'
+                    f'{self.config.get("Influx Retention Policy", "")}'
+                    ).rstrip("/")
+            self.organisation = "-"
+        elif self.version == 2:
+            token = self.config["Influx Token"]
+            self.bucket = self.config["Influx Bucket"]
+            self.organisation = self.config["Influx Organisation"]
+        else:
+            raise ValueError(
+                f"Influx Version {self.version} is not supported, use 1 or 2"
+            )
+        self.client = InfluxDBClient(url=self.url,
+            token=token,
+            org=self.organisation,
             timeout=150000)
         self.write_client = self.client.write_api(write_options=SYNCHRONOUS)
+        self.retry_policy = RetryPolicy(
+                retries=self.config.get("Influx Write Retries", 3),
+                backoff=self.config.get("Influx Retry Backoff", 1)
+                )
+        self.batch_size = int(self.config.get("Influx Batch Size", 5000))
+        self.buffer = list()
+        self.write_count = 0
+
+    def is_available(self):
+        """ Checks the InfluxDB 2.0 database can be reached
+
+        Used before downloading anything so a run doesn't spend time
+        downloading measurements that can't be written
+
+        Returns:
+            True if the database responded to a ping
+        """
+        try:
+            return self.client.ping()
+        except Exception:
+            return False
 
     def write_container_list(self, list_of_containers):
         """ Writes list of containers to an InfluxDB 2.0 database
@@ -85,7 +210,42 @@ class InfluxWriter:
             - "tags": Tags corresponding to the particular measurement
                       e.g Sensor ID, measurement flag (Valid etc)
 
+        Containers are added to buffer and written batch_size at a time,
+        so one request can hold containers from several calls. flush must
+        be called to write the last, partial batch
+
+        """
+        if self.batch_size <= 0:
+            self.write_batch(list_of_containers)
+            return
+        self.buffer.extend(list_of_containers)
+        while len(self.buffer) >= self.batch_size:
+            self.write_batch(self.buffer[:self.batch_size])
+            self.buffer = self.buffer[self.batch_size:]
+
+    def write_batch(self, batch):
+        """ Sends one batch of containers in a single write request
+
+        Transient failures (server errors, timeouts, dropped connections)
+        are retried according to retry_policy, client errors are not
+
+        Keyword arguments:
+            batch (list): Containers to write
+        """
+        if len(batch) == 0:
+            return
+        self.retry_policy.run(
+                lambda: self.write_client.write(
+                    self.bucket,
+                    self.organisation,
+                    batch
+                    ),
+                is_transient_write_error
+                )
+        self.write_count += 1
+
+    def flush(self):
+        """ Writes any containers left in the buffer
         """
-        self.write_client.write(self.config["Influx Bucket"],
-            self.config["Influx Organisation"],
-            list_of_containers)
+        self.write_batch(self.buffer)
+        self.buffer = list()
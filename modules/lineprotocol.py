@@ -0,0 +1,323 @@
+""" Contains classes and methods that write data to gzipped line protocol
+files
+
+Instead of sending data to an InfluxDB database directly, measurements are
+converted to InfluxDB line protocol and saved to a gzip compressed file,
+which can be loaded later using the influx CLI
+(influx write --compression gzip -f file.lp.gz). This allows the export to
+run somewhere the database can't be reached from. Points can also be saved
+in the JSON form ({measurement, tags, fields, timestamp}) accepted by some
+ingestion gateways. It accepts the same list of containers as InfluxWriter
+
+    Classes:
+        LineProtocolFileWriter: Writes containers to a gzipped line
+        protocol file
+
+        LineProtocolStreamWriter: Writes containers as line protocol to a
+        stream such as stdout
+
+    Functions:
+        escape_key: Escapes measurement names, tag keys, tag values and
+        field keys for line protocol
+
+        timestamp_nanoseconds: Converts a measurement time to nanoseconds
+        since epoch
+
+"""
+
+__author__ = "Idris Hayward"
+__copyright__ = "2021, Idris Hayward"
+__credits__ = ["Idris Hayward"]
+__license__ = "GNU General Public License v3.0"
+__version__ = "1.0"
+__maintainer__ = "Idris Hayward"
+__email__ = "CaderIdrisGH@outlook.com"
+__status__ = "Stable Release"
+
+import datetime as dt
+import gzip
+import json
+import os
+import sys
+
+from modules.partition import (partition_container_list, partitioned_path,
+                               validate_partition)
+
+
+def escape_key(key, characters=", ="):
+    """ Escapes measurement names, tag keys, tag values and field keys for
+    line protocol
+
+    Keyword arguments:
+        key (str): String to escape
+
+        characters (str): Characters to escape with a backslash. Measurement
+        names only need commas and spaces escaped
+
+    Returns:
+        Escaped string
+    """
+    key = str(key).replace("\\", "\\\\")
+    for character in characters:
+        key = key.replace(character, f"\\{character}")
+    return key
+
+
+def timestamp_nanoseconds(timestamp):
+    """ Converts a measurement time to nanoseconds since epoch
+
+    Keyword arguments:
+        timestamp (datetime): Time to convert, treated as UTC if it has no
+        timezone
+
+    Returns:
+        Nanoseconds since 1970-01-01 as int
+    """
+    if timestamp.tzinfo is None:
+        timestamp = timestamp.replace(tzinfo=dt.timezone.utc)
+    return (
+            (timestamp - dt.datetime(1970, 1, 1, tzinfo=dt.timezone.utc))
+            // dt.timedelta(microseconds=1)
+            ) * 1000
+
+
+class LineProtocolFileWriter:
+    """ Writes containers to a gzipped line protocol file
+
+    All measurements from a run are written to the same file. The file is
+    emptied when the class is initialised and each list of containers is
+    appended as its own gzip member, so the file can be read even if the
+    export is stopped part way through. If "File Partition" is Year or
+    Month, measurements are instead written to one file per year or month,
+    named by adding the partition to the path (e.g aurn_2019.lp.gz). Each
+    partitioned file is emptied the first time it is written to in a run
+
+    Attributes:
+        config (dict): Contains the path of the file to save to
+
+        path (str): Path of the gzipped line protocol file
+
+        body_format (str): "Line Protocol" or "JSON". JSON files have one
+        array of points per line, one line for each list of containers
+
+        partition (str): None, Year or Month, "File Partition" from config
+        (default None)
+
+        written_paths (set): Paths of partitioned files written this run
+
+    Methods:
+        container_to_line: Converts a container to a line of line protocol
+
+        container_to_point: Converts a container to a JSON point
+
+        write_container_list: Appends list of containers to the file
+
+        write_lines: Writes list of containers to a file as a single gzip
+        member
+    """
+    def __init__(self, lp_config):
+        """ Initialises class and empties the output file
+
+            Keyword arguments:
+                lp_config (dict): The following keys are required:
+                    - "Line Protocol File": Path to save gzipped line
+                                            protocol to, None if lines
+                                            aren't saved to a file (e.g
+                                            LineProtocolStreamWriter)
+                The following keys are optional:
+                    - "Line Protocol Body Format": Line Protocol (default)
+                                                  or JSON
+
+            Raises:
+                ValueError if the body format isn't Line Protocol or JSON,
+                or "File Partition" isn't None, Year or Month
+        """
+        self.config = lp_config
+        self.path = self.config["Line Protocol File"]
+        self.body_format = self.config.get(
+                "Line Protocol Body Format", "Line Protocol"
+                )
+        if self.body_format not in ["Line Protocol", "JSON"]:
+            raise ValueError(
+                f'"{self.body_format}" is not a valid body format, use '
+                f"Line Protocol or JSON"
+            )
+        self.partition = validate_partition(
+                self.config.get("File Partition", "None")
+                )
+        self.written_paths = set()
+        if self.path is None:
+            return
+        directory = os.path.dirname(self.path)
+        if directory != "":
+            os.makedirs(directory, exist_ok=True)
+        if self.partition == "None":
+            with gzip.open(self.path, "wt", encoding="utf-8"):
+                pass
+
+    def container_to_line(self, container):
+        """ Converts a container to a line of line protocol
+
+        Integer fields are written with the i suffix, booleans as true or
+        false, other numeric fields as floats and string fields are quoted,
+        matching the line protocol influxdb_client writes. Times without a
+        timezone are treated as UTC
+
+        Keyword arguments:
+            container (dict): Container in the format used by InfluxWriter
+
+        Returns:
+            Line protocol string, without a trailing newline. Empty string
+            if the container has no fields
+        """
+        fields = list()
+        for field, value in container["fields"].items():
+            if isinstance(value, str):
+                value = value.replace("\\", "\\\\").replace('"', '\\"')
+                fields.append(f'{escape_key(field)}="{value}"')
+            elif isinstance(value, bool):
+                fields.append(f"{escape_key(field)}={str(value).lower()}")
+            elif isinstance(value, int):
+                fields.append(f"{escape_key(field)}={value}i")
+            else:
+                fields.append(f"{escape_key(field)}={float(value)!r}")
+        if len(fields) == 0:
+            return ""
+        tags = [
+                f"{escape_key(tag)}={escape_key(value)}"
+                for tag, value in sorted(container["tags"].items())
+                if str(value) != ""
+                ]
+        series = ",".join(
+                [escape_key(container["measurement"], ", ")] + tags
+                )
+        return (f"{series} {','.join(fields)} "
+                f"{timestamp_nanoseconds(container['time'])}")
+
+    def container_to_point(self, container):
+        """ Converts a container to a JSON point
+
+        The point has the same tags, fields and timestamp as the line made
+        by container_to_line, without the escaping line protocol needs
+
+        Keyword arguments:
+            container (dict): Container in the format used by InfluxWriter
+
+        Returns:
+            Dict with measurement, tags, fields and timestamp (nanoseconds)
+            keys. None if the container has no fields
+        """
+        if len(container["fields"]) == 0:
+            return None
+        return {
+                "measurement": container["measurement"],
+                "tags": {
+                    str(tag): str(value) for tag, value in
+                    sorted(container["tags"].items()) if str(value) != ""
+                    },
+                "fields": {
+                    field: (
+                        value if isinstance(value, (str, int))
+                        else float(value)
+                        )
+                    for field, value in container["fields"].items()
+                    },
+                "timestamp": timestamp_nanoseconds(container["time"])
+                }
+
+    def write_container_list(self, list_of_containers):
+        """ Appends list of containers to the file, or the file for each
+        partition they are in
+
+        Keyword arguments:
+            list_of_containers (list): Containers to write
+        """
+        partitions = partition_container_list(
+                list_of_containers, self.partition
+                )
+        for name, containers in partitions.items():
+            path = partitioned_path(self.path, name)
+            mode = "at"
+            if name is not None and path not in self.written_paths:
+                mode = "wt"
+                self.written_paths.add(path)
+            self.write_lines(path, mode, containers)
+
+    def write_lines(self, path, mode, list_of_containers):
+        """ Writes list of containers to a file as a single gzip member
+
+        Keyword arguments:
+            path (str): Path of the gzipped file
+
+            mode (str): "wt" to empty the file first or "at" to append
+
+            list_of_containers (list): Containers to write
+        """
+        if self.body_format == "JSON":
+            points = [
+                    self.container_to_point(container)
+                    for container in list_of_containers
+                    ]
+            points = [point for point in points if point is not None]
+            lines = [json.dumps(points)] if len(points) > 0 else list()
+        else:
+            lines = [
+                    self.container_to_line(container)
+                    for container in list_of_containers
+                    ]
+            lines = [line for line in lines if line != ""]
+        # A new partition file is still emptied if there is nothing to
+        # write, so measurements from an earlier run aren't left in it
+        if len(lines) == 0 and mode == "at":
+            return
+        with gzip.open(path, mode, encoding="utf-8") as lp_file:
+            if len(lines) > 0:
+                lp_file.write("\n".join(lines) + "\n")
+
+
+class LineProtocolStreamWriter(LineProtocolFileWriter):
+    """ Writes containers as line protocol to a stream such as stdout
+
+    Used for dry runs, so the exact lines that would be sent to InfluxDB
+    can be checked without writing to a real bucket. Lines are the same as
+    those saved by LineProtocolFileWriter. Attributes of
+    LineProtocolFileWriter are set as for a writer with no file
+
+    Attributes:
+        stream (file object): Text stream lines are written to
+
+        path (None): Lines aren't saved to a file
+
+        body_format (str): Always "Line Protocol"
+
+        partition (str): Always "None"
+
+        line_count (int): Number of lines written
+
+    Methods:
+        write_container_list: Writes list of containers to the stream
+    """
+    def __init__(self, stream=None):
+        """ Initialises class
+
+            Keyword arguments:
+                stream (file object): Text stream to write to, stdout if
+                None
+        """
+        super().__init__({"Line Protocol File": None})
+        self.stream = stream if stream is not None else sys.stdout
+        self.line_count = 0
+
+    def write_container_list(self, list_of_containers):
+        """ Writes list of containers to the stream
+
+        Keyword arguments:
+            list_of_containers (list): Containers to write
+        """
+        for container in list_of_containers:
+            line = self.container_to_line(container)
+            if line == "":
+                continue
+            self.stream.write(f"{line}\n")
+            self.line_count += 1
+        self.stream.flush()
@@ -0,0 +1,521 @@
+""" Contains classes and methods that keep a record of what was exported
+
+Every time a list of containers is exported, the number of measurements
+for each pollutant and the time range they cover is recorded. Once the
+export has finished the record can be saved as a json or csv file, giving
+a durable index of what was written
+
+A checksum of each day's measurements can also be recorded, so a later run
+can tell which days have changed (e.g after ratification) and only export
+those. This adds roughly 5 kB per site, pollutant and year to the manifest.
+Whole days are compared rather than single measurements to keep the
+manifest small, so unchanged measurements on a changed day are written
+again
+
+    Classes:
+        ExportManifest: Records pollutant, year, point count and time range
+        for every site exported
+
+"""
+
+__author__ = "Idris Hayward"
+__copyright__ = "2021, Idris Hayward"
+__credits__ = ["Idris Hayward"]
+__license__ = "GNU General Public License v3.0"
+__version__ = "1.0"
+__maintainer__ = "Idris Hayward"
+__email__ = "CaderIdrisGH@outlook.com"
+__status__ = "Stable Release"
+
+import csv
+import datetime as dt
+import json
+import os
+import zlib
+
+from modules.aurn import is_measurement
+
+
+class ExportManifest:
+    """ Records pollutant, year, point count and time range for every
+    site exported
+
+    Attributes:
+        entries (dict): Manifest entries, keyed by (site code, pollutant,
+        year)
+
+        record_checksums (bool): Record a checksum of each day's
+        measurements for every entry
+
+    Methods:
+        add_container_list: Record the pollutants in a list of exported
+        containers
+
+        as_list: Return manifest entries as list of dicts
+
+        save: Save manifest to json or csv file
+
+        load: Load manifest saved by a previous run
+
+        contains: Check if a site, pollutant and year has been exported
+
+        filter_container_list: Remove pollutants already exported from a
+        list of containers
+
+        is_ratified: Check if every pollutant exported for a site and year
+        was ratified
+
+        filter_newly_ratified: Keep only pollutants that have been ratified
+        since they were exported
+
+        remove: Remove an entry so it can be recorded again
+
+        remove_site_year: Remove every entry for a site and year
+
+        day_checksums: Checksum each day's measurements of each pollutant
+
+        replace_container_list: Replace the entries for the pollutants in a
+        list of exported containers
+
+        filter_changed: Keep only measurements on days that have changed
+        since they were exported
+    """
+    columns = [
+            "site_code",
+            "pollutant",
+            "year",
+            "point_count",
+            "first_ts",
+            "last_ts",
+            "ratified",
+            "day_checksums"
+            ]
+
+    def __init__(self, record_checksums=False):
+        """ Initialises class
+
+        Keyword arguments:
+            record_checksums (bool): Record a checksum of each day's
+            measurements for every entry
+        """
+        self.entries = dict()
+        self.record_checksums = record_checksums
+
+    def add_container_list(self, site_code, year, list_of_containers):
+        """ Record the pollutants in a list of exported containers
+
+        Keyword arguments:
+            site_code (str): Download code of the site the containers are
+            from
+
+            year (int): Year the measurements were made
+
+            list_of_containers (list): Containers that were exported
+
+        Variables:
+            key (tuple): Site code, pollutant and year of the entry being
+            updated
+
+            status (str): Status of the measurement, "V" if it has been
+            ratified (verified)
+        """
+        for container in list_of_containers:
+            for pollutant, value in container["fields"].items():
+                if not is_measurement(pollutant, value):
+                    continue
+                key = (site_code, pollutant, year)
+                if key not in self.entries:
+                    self.entries[key] = {
+                            "site_code": site_code,
+                            "pollutant": pollutant,
+                            "year": year,
+                            "point_count": 0,
+                            "first_ts": container["time"],
+                            "last_ts": container["time"],
+                            "ratified": True,
+                            "day_checksums": dict()
+                            }
+                entry = self.entries[key]
+                status = container["tags"].get(f"{pollutant} status", "")
+                entry["ratified"] = (
+                        entry["ratified"] and str(status).startswith("V")
+                        )
+                entry["point_count"] += 1
+                entry["first_ts"] = min(entry["first_ts"], container["time"])
+                entry["last_ts"] = max(entry["last_ts"], container["time"])
+        if self.record_checksums:
+            for pollutant, checksums in self.day_checksums(
+                    list_of_containers
+                    ).items():
+                self.entries[(site_code, pollutant, year)][
+                        "day_checksums"
+                        ].update(checksums)
+
+    def as_list(self):
+        """ Return manifest entries as list of dicts
+
+        Returns:
+            List of manifest entries, timestamps in ISO8601 format
+        """
+        entry_list = list()
+        for entry in self.entries.values():
+            entry = entry.copy()
+            entry["first_ts"] = entry["first_ts"].isoformat()
+            entry["last_ts"] = entry["last_ts"].isoformat()
+            entry_list.append(entry)
+        return entry_list
+
+    def save(self, path):
+        """ Save manifest to json or csv file
+
+        The format is chosen from the file extension, csv if the path ends
+        in .csv and json otherwise
+
+        Keyword arguments:
+            path (str): Path to save manifest to
+        """
+        with open(path, "w", newline="") as manifest_file:
+            if path.lower().endswith(".csv"):
+                writer = csv.DictWriter(manifest_file, fieldnames=self.columns)
+                writer.writeheader()
+                for entry in self.as_list():
+                    entry["day_checksums"] = json.dumps(
+                            entry["day_checksums"]
+                            )
+                    writer.writerow(entry)
+            else:
+                json.dump(self.as_list(), manifest_file, indent=2)
+
+    @classmethod
+    def load(cls, path):
+        """ Load manifest saved by a previous run
+
+        Missing files and entries that can't be read are skipped rather
+        than stopping the export, as a manifest from an interrupted run
+        may be partial. Anything missing from it is exported again
+
+        Keyword arguments:
+            path (str): Path to manifest, csv if it ends in .csv and json
+            otherwise
+
+        Variables:
+            raw_entries (list): Entries read from the file
+
+        Returns:
+            ExportManifest containing the entries in the file
+        """
+        manifest = cls()
+        if not os.path.isfile(path):
+            return manifest
+        with open(path, "r", newline="") as manifest_file:
+            try:
+                if path.lower().endswith(".csv"):
+                    raw_entries = list(csv.DictReader(manifest_file))
+                else:
+                    raw_entries = json.load(manifest_file)
+            except (json.decoder.JSONDecodeError, csv.Error):
+                return manifest
+        for raw_entry in raw_entries:
+            try:
+                entry = {
+                        "site_code": str(raw_entry["site_code"]),
+                        "pollutant": str(raw_entry["pollutant"]),
+                        "year": int(raw_entry["year"]),
+                        "point_count": int(raw_entry["point_count"]),
+                        "first_ts": dt.datetime.fromisoformat(
+                            raw_entry["first_ts"]
+                            ),
+                        "last_ts": dt.datetime.fromisoformat(
+                            raw_entry["last_ts"]
+                            ),
+                        # Manifests from older versions don't record
+                        # ratification, so assume provisional
+                        "ratified": str(
+                            raw_entry.get("ratified", False)
+                            ).lower() == "true",
+                        "day_checksums": raw_entry.get("day_checksums") or {}
+                        }
+                if isinstance(entry["day_checksums"], str):
+                    entry["day_checksums"] = json.loads(
+                            entry["day_checksums"]
+                            )
+                if not isinstance(entry["day_checksums"], dict):
+                    raise TypeError("day_checksums is not a dict")
+            except (KeyError, TypeError, ValueError):
+                continue
+            manifest.entries[
+                    (entry["site_code"], entry["pollutant"], entry["year"])
+                    ] = entry
+        return manifest
+
+    def contains(self, site_code, pollutant, year):
+        """ Check if a site, pollutant and year has been exported
+
+        Keyword arguments:
+            site_code (str): Download code of the site
+
+            pollutant (str): Name of the pollutant
+
+            year (int): Year of the measurements
+
+        Returns:
+            True if the combination is in the manifest
+        """
+        return (site_code, pollutant, int(year)) in self.entries
+
+    def filter_container_list(self, site_code, year, list_of_containers):
+        """ Remove pollutants already exported from a list of containers
+
+        Pollutants in the manifest for the site and year are removed from
+        each container's fields. Containers left with no pollutants are
+        removed
+
+        Keyword arguments:
+            site_code (str): Download code of the site the containers are
+            from
+
+            year (int): Year the measurements were made
+
+            list_of_containers (list): Containers to filter
+
+        Variables:
+            has_pollutant (bool): Does the container have any pollutants
+            left to export?
+
+        Returns:
+            Tuple of filtered list of containers and set of pollutants
+            that were removed
+        """
+        filtered_containers = list()
+        skipped_pollutants = set()
+        for container in list_of_containers:
+            fields = dict()
+            has_pollutant = False
+            for field, value in container["fields"].items():
+                is_pollutant = is_measurement(field, value)
+                if is_pollutant and self.contains(site_code, field, year):
+                    skipped_pollutants.add(field)
+                    continue
+                if is_pollutant:
+                    has_pollutant = True
+                fields[field] = value
+            if has_pollutant:
+                container = container.copy()
+                container["fields"] = fields
+                filtered_containers.append(container)
+        return filtered_containers, skipped_pollutants
+
+    def is_ratified(self, site_code, year):
+        """ Check if every pollutant exported for a site and year was
+        ratified
+
+        Keyword arguments:
+            site_code (str): Download code of the site
+
+            year (int): Year of the measurements
+
+        Returns:
+            True if the site and year are in the manifest and all of their
+            pollutants were ratified
+        """
+        site_entries = [
+                entry for key, entry in self.entries.items()
+                if key[0] == site_code and key[2] == int(year)
+                ]
+        return (len(site_entries) > 0 and
+                all(entry["ratified"] for entry in site_entries))
+
+    def filter_newly_ratified(self, site_code, year, list_of_containers):
+        """ Keep only pollutants that have been ratified since they were
+        exported
+
+        DEFRA ratifies provisional measurements some months after they are
+        made, which can change their values. A pollutant is kept if it is
+        in the manifest as provisional and every one of its measurements
+        in list_of_containers is now ratified. Containers left with no
+        pollutants are removed
+
+        Keyword arguments:
+            site_code (str): Download code of the site the containers are
+            from
+
+            year (int): Year the measurements were made
+
+            list_of_containers (list): Containers to filter
+
+        Variables:
+            ratified_pollutants (dict): Whether every measurement of each
+            pollutant in list_of_containers is ratified
+
+        Returns:
+            Tuple of filtered list of containers and set of pollutants
+            that were kept
+        """
+        ratified_pollutants = dict()
+        for container in list_of_containers:
+            for field, value in container["fields"].items():
+                if not is_measurement(field, value):
+                    continue
+                status = container["tags"].get(f"{field} status", "")
+                ratified_pollutants[field] = (
+                        ratified_pollutants.get(field, True) and
+                        str(status).startswith("V")
+                        )
+        newly_ratified = {
+                pollutant for pollutant, ratified in
+                ratified_pollutants.items()
+                if ratified and self.contains(site_code, pollutant, year) and
+                not self.entries[(site_code, pollutant, int(year))]["ratified"]
+                }
+        filtered_containers = list()
+        for container in list_of_containers:
+            fields = dict()
+            has_pollutant = False
+            for field, value in container["fields"].items():
+                is_pollutant = is_measurement(field, value)
+                if is_pollutant and field not in newly_ratified:
+                    continue
+                if is_pollutant:
+                    has_pollutant = True
+                fields[field] = value
+            if has_pollutant:
+                container = container.copy()
+                container["fields"] = fields
+                filtered_containers.append(container)
+        return filtered_containers, newly_ratified
+
+    def remove(self, site_code, pollutant, year):
+        """ Remove an entry so it can be recorded again
+
+        Keyword arguments:
+            site_code (str): Download code of the site
+
+            pollutant (str): Name of the pollutant
+
+            year (int): Year of the measurements
+        """
+        self.entries.pop((site_code, pollutant, int(year)), None)
+
+    def remove_site_year(self, site_code, year):
+        """ Remove every entry for a site and year, e.g when only some of
+        its measurements were exported
+
+        Keyword arguments:
+            site_code (str): Download code of the site
+
+            year (int): Year of the measurements
+        """
+        for key in list(self.entries.keys()):
+            if key[0] == site_code and key[2] == int(year):
+                del self.entries[key]
+
+    def day_checksums(self, list_of_containers):
+        """ Checksum each day's measurements of each pollutant
+
+        Measurements are grouped by the day of the hour they measured, as
+        AURN timestamps mark the end of the hour. The checksum covers the
+        time and value of every measurement in the day
+
+        Keyword arguments:
+            list_of_containers (list): Containers to checksum
+
+        Variables:
+            day_values (dict): Time and value strings for each pollutant
+            and day
+
+        Returns:
+            Dict of pollutant to dict of day (YYYY-MM-DD) to checksum
+        """
+        day_values = dict()
+        for container in list_of_containers:
+            day = (container["time"] - dt.timedelta(hours=1)).date()
+            for pollutant, value in container["fields"].items():
+                if not is_measurement(pollutant, value):
+                    continue
+                day_values.setdefault(pollutant, dict()).setdefault(
+                        day.isoformat(), list()
+                        ).append(f"{container['time'].isoformat()}={value!r}")
+        return {
+                pollutant: {
+                    day: format(
+                        zlib.crc32("\n".join(sorted(values)).encode()),
+                        "08x"
+                        )
+                    for day, values in days.items()
+                    }
+                for pollutant, days in day_values.items()
+                }
+
+    def replace_container_list(self, site_code, year, list_of_containers):
+        """ Replace the entries for the pollutants in a list of exported
+        containers
+
+        Used when measurements are exported again, so the entries describe
+        the latest export rather than adding to the previous one
+
+        Keyword arguments:
+            site_code (str): Download code of the site the containers are
+            from
+
+            year (int): Year the measurements were made
+
+            list_of_containers (list): Containers that were exported
+        """
+        for pollutant in self.day_checksums(list_of_containers).keys():
+            self.remove(site_code, pollutant, year)
+        self.add_container_list(site_code, year, list_of_containers)
+
+    def filter_changed(self, site_code, year, list_of_containers):
+        """ Keep only measurements on days that have changed since they
+        were exported
+
+        A day is changed if its checksum differs from the one in the
+        manifest, or the manifest has no checksum for it. Containers left
+        with no pollutants are removed
+
+        Keyword arguments:
+            site_code (str): Download code of the site the containers are
+            from
+
+            year (int): Year the measurements were made
+
+            list_of_containers (list): Containers to filter
+
+        Variables:
+            new_checksums (dict): Checksums of each day in
+            list_of_containers
+
+            changed_days (set): Pollutant and day of every changed day
+
+        Returns:
+            Tuple of filtered list of containers and number of measurements
+            removed as unchanged
+        """
+        new_checksums = self.day_checksums(list_of_containers)
+        changed_days = set()
+        for pollutant, checksums in new_checksums.items():
+            entry = self.entries.get((site_code, pollutant, int(year)))
+            old_checksums = dict() if entry is None else entry["day_checksums"]
+            for day, checksum in checksums.items():
+                if old_checksums.get(day) != checksum:
+                    changed_days.add((pollutant, day))
+        filtered_containers = list()
+        unchanged_count = 0
+        for container in list_of_containers:
+            day = (container["time"] - dt.timedelta(hours=1)).date()
+            fields = dict()
+            has_pollutant = False
+            for field, value in container["fields"].items():
+                is_pollutant = is_measurement(field, value)
+                if (is_pollutant and
+                        (field, day.isoformat()) not in changed_days):
+                    unchanged_count += 1
+                    continue
+                if is_pollutant:
+                    has_pollutant = True
+                fields[field] = value
+            if has_pollutant:
+                container = container.copy()
+                container["fields"] = fields
+                filtered_containers.append(container)
+        return filtered_containers, unchanged_count
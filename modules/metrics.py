@@ -0,0 +1,104 @@
+""" Contains classes that serve export progress as Prometheus metrics
+
+When the program is left running for a long backfill, a small HTTP server
+can be started that serves counters (requests made, failed requests,
+measurements written) and the current phase in the Prometheus text
+format, so the export can be monitored without watching the console
+
+    Classes:
+        MetricsServer: Serves metrics over HTTP in a background thread
+
+"""
+
+__author__ = "Idris Hayward"
+__copyright__ = "2021, Idris Hayward"
+__credits__ = ["Idris Hayward"]
+__license__ = "GNU General Public License v3.0"
+__version__ = "1.0"
+__maintainer__ = "Idris Hayward"
+__email__ = "CaderIdrisGH@outlook.com"
+__status__ = "Stable Release"
+
+import threading
+from http.server import BaseHTTPRequestHandler, ThreadingHTTPServer
+
+
+class MetricsServer:
+    """ Serves metrics over HTTP in a background thread
+
+    Attributes:
+        collect (callable): Returns a dict of metric name to value when
+        called. A "phase" key is served as a label rather than a value
+
+        server (ThreadingHTTPServer): The HTTP server
+
+        thread (threading.Thread): Thread the server runs in
+
+    Methods:
+        metrics_text: Formats the current metrics in the Prometheus text
+        format
+
+        start: Starts serving metrics in a background thread
+
+        stop: Stops the server
+    """
+    def __init__(self, address, port, collect):
+        """ Initialises class
+
+        Keyword arguments:
+            address (str): Address to bind to e.g 127.0.0.1
+
+            port (int): Port to bind to
+
+            collect (callable): Returns a dict of metric name to value
+        """
+        self.collect = collect
+        metrics_server = self
+
+        class MetricsHandler(BaseHTTPRequestHandler):
+            def do_GET(self):
+                body = metrics_server.metrics_text().encode("utf-8")
+                self.send_response(200)
+                self.send_header(
+                        "Content-Type", "text/plain; version=0.0.4"
+                        )
+                self.send_header("Content-Length", str(len(body)))
+                self.end_headers()
+                self.wfile.write(body)
+
+            def log_message(self, format, *args):
+                # Requests would otherwise be printed over the progress
+                # output
+                pass
+
+        self.server = ThreadingHTTPServer((address, int(port)), MetricsHandler)
+        self.thread = threading.Thread(
+                target=self.server.serve_forever,
+                daemon=True
+                )
+
+    def metrics_text(self):
+        """ Formats the current metrics in the Prometheus text format
+
+        Returns:
+            String with one line per metric, all prefixed with aurn_
+        """
+        lines = list()
+        for name, value in self.collect().items():
+            if name == "phase":
+                lines.append(f'aurn_phase{{phase="{value}"}} 1')
+            else:
+                lines.append(f"# TYPE aurn_{name} counter")
+                lines.append(f"aurn_{name} {value}")
+        return "\n".join(lines) + "\n"
+
+    def start(self):
+        """ Starts serving metrics in a background thread
+        """
+        self.thread.start()
+
+    def stop(self):
+        """ Stops the server
+        """
+        self.server.shutdown()
+        self.server.server_close()
@@ -0,0 +1,250 @@
+""" Contains classes and methods that write data to an OpenTSDB database
+
+Communicates with an OpenTSDB instance, location specified by config
+file and writes data to it synchronously via the HTTP /api/put endpoint.
+It accepts the same list of containers as InfluxWriter so the two can be
+swapped without changing the export loop
+
+    Classes:
+        OpenTSDBWriter: Handles connection to OpenTSDB database and
+        writes data to it
+
+    Functions:
+        opentsdb_safe: Replaces characters OpenTSDB does not allow in
+        metric names and tag values
+
+        is_transient_put_error: Checks if a failed put request is worth
+        retrying
+
+"""
+
+__author__ = "Idris Hayward"
+__copyright__ = "2021, Idris Hayward"
+__credits__ = ["Idris Hayward"]
+__license__ = "GNU General Public License v3.0"
+__version__ = "1.0"
+__maintainer__ = "Idris Hayward"
+__email__ = "CaderIdrisGH@outlook.com"
+__status__ = "Stable Release"
+
+import re
+
+import datetime as dt
+import requests as req
+
+from modules.aurn import is_measurement
+from modules.retry import RetryPolicy
+
+
+def opentsdb_safe(string_to_clean):
+    """ Replaces characters OpenTSDB does not allow in metric names and
+    tag keys/values with underscores
+
+    OpenTSDB only accepts a-z, A-Z, 0-9, -, _, . and / in metric names
+    and tags. AURN site names and pollutants contain spaces, commas and
+    brackets so they need cleaning before export.
+
+    Keyword arguments:
+        string_to_clean (str): String to be made OpenTSDB safe
+
+    Returns:
+        Input string with all disallowed characters replaced by an
+        underscore
+    """
+    return re.sub(r"[^a-zA-Z0-9\-_./]", "_", str(string_to_clean))
+
+
+def is_transient_put_error(error):
+    """ Checks if a failed put request is worth retrying
+
+    Server errors (5xx), timeouts and dropped connections are retried.
+    Client errors (4xx) mean OpenTSDB rejected the datapoints, sending
+    them again won't help so they aren't retried.
+
+    Keyword arguments:
+        error (Exception): Error raised when writing
+
+    Returns:
+        True if the write should be retried
+    """
+    if isinstance(error, req.HTTPError):
+        return (error.response is None or
+                error.response.status_code >= 500)
+    return isinstance(error, (req.ConnectionError, req.Timeout))
+
+
+class OpenTSDBWriter:
+    """ Handles connection to OpenTSDB database and writes data to it
+
+    An instance of this class sends data to an OpenTSDB database, with
+    all connection info passed to it via a config file. Each pollutant
+    measured becomes its own metric, with the site and environment info
+    attached as tags
+
+    Attributes:
+        config (dict): Contains all information needed to connect and
+        write data to OpenTSDB database
+
+        put_url (str): Url of the /api/put endpoint
+
+        site_tags (list): Metadata tags attached to every data point
+
+        request_timeout (float): Seconds to wait for OpenTSDB to connect or
+        respond, "Request Timeout" from config (default 60)
+
+        retry_policy (RetryPolicy): How many times and how long to wait
+        before retrying a write that failed for a transient reason.
+        "OpenTSDB Write Retries" and "OpenTSDB Retry Backoff" from config,
+        default 3 and 1
+
+        batch_size (int): Number of datapoints sent in each put request,
+        "OpenTSDB Batch Size" from config (default 50). 0 or less sends
+        each list of containers in one request
+
+        write_count (int): Number of put requests sent
+
+    Methods:
+        container_list_to_datapoints: Converts list of containers to
+        list of OpenTSDB datapoints
+
+        write_container_list: Writes list of containers to an OpenTSDB
+        database
+    """
+    site_tags = [
+            "Site Name",
+            "Download Code",
+            "Environment Type",
+            "Zone"
+            ]
+
+    def __init__(self, opentsdb_config):
+        """ Initialises class
+
+            Keyword arguments:
+                opentsdb_config (dict): Contains all info relevant to
+                connecting to OpenTSDB database. The following keys
+                are required:
+                    - "OpenTSDB IP": The IP address of the OpenTSDB
+                                     database, 'localhost' if on same
+                                     machine
+                    - "OpenTSDB Port": Port of OpenTSDB database,
+                                       usually 4242
+                    - "OpenTSDB Metric Prefix": Prefixed to pollutant
+                                                names to make metric
+                                                names e.g "aurn."
+                The following keys are optional:
+                    - "Request Timeout": Seconds to wait for a
+                                         response, default 60
+                    - "OpenTSDB Write Retries": Times to retry a
+                                                failed write, default 3
+                    - "OpenTSDB Retry Backoff": Seconds to wait
+                                                before first retry,
+                                                default 1
+                    - "OpenTSDB Batch Size": Datapoints per put
+                                             request, default 50
+        """
+        self.config = opentsdb_config
+        self.site_tags = self.site_tags[:-1] + [
+                self.config.get("Zone Tag", "Zone")
+                ]
+        self.put_url = (f'http://{self.config["OpenTSDB IP"]}'
+                        f':{self.config["OpenTSDB Port"]}/api/put')
+        self.request_timeout = self.config.get("Request Timeout", 60)
+        self.retry_policy = RetryPolicy(
+                retries=self.config.get("OpenTSDB Write Retries", 3),
+                backoff=self.config.get("OpenTSDB Retry Backoff", 1)
+                )
+        self.batch_size = int(self.config.get("OpenTSDB Batch Size", 50))
+        self.write_count = 0
+
+    def container_list_to_datapoints(self, list_of_containers):
+        """ Converts list of containers to list of OpenTSDB datapoints
+
+        OpenTSDB only stores one value per datapoint, so every pollutant
+        in a container's fields becomes its own datapoint. The pollutant's
+        status and unit tags are attached to its datapoint only.
+
+        Keyword arguments:
+            list_of_containers (list): Containers in the format used by
+            InfluxWriter
+
+        Variables:
+            timestamp (int): Measurement time in seconds since epoch.
+            Measurement times have no timezone but are GMT, so are
+            converted as UTC rather than the machine's local time
+
+            tags (dict): Tags attached to the datapoint
+
+        Returns:
+            List of dicts in OpenTSDB /api/put format
+        """
+        datapoints = list()
+        for container in list_of_containers:
+            measurement_time = container["time"]
+            if measurement_time.tzinfo is None:
+                measurement_time = measurement_time.replace(
+                        tzinfo=dt.timezone.utc
+                        )
+            timestamp = int(measurement_time.timestamp())
+            for pollutant, value in container["fields"].items():
+                # String fields are skipped as OpenTSDB only stores numbers
+                if not is_measurement(pollutant, value):
+                    continue
+                tags = dict()
+                for tag in self.site_tags:
+                    if container["tags"].get(tag) is not None:
+                        tags[opentsdb_safe(tag)] = opentsdb_safe(
+                                container["tags"][tag]
+                                )
+                for suffix in ["status", "unit"]:
+                    tag_value = container["tags"].get(f"{pollutant} {suffix}")
+                    if tag_value is not None:
+                        tags[suffix] = opentsdb_safe(tag_value)
+                datapoints.append(
+                        {
+                            "metric": opentsdb_safe(
+                                f'{self.config["OpenTSDB Metric Prefix"]}'
+                                f'{pollutant}'
+                                ),
+                            "timestamp": timestamp,
+                            "value": value,
+                            "tags": tags
+                        }
+                    )
+        return datapoints
+
+    def write_container_list(self, list_of_containers):
+        """ Writes list of containers to an OpenTSDB database
+
+        Takes list of containers as input, converts them to OpenTSDB
+        datapoints and writes them batch_size datapoints at a time, as
+        OpenTSDB limits the size of a put request. The containers must
+        have the same keys as those passed to
+        InfluxWriter.write_container_list. Transient failures (server
+        errors, timeouts, dropped connections) are retried according to
+        retry_policy, client errors are not. Only the failed batch is sent
+        again
+
+        Keyword arguments:
+            list_of_containers (list): Containers to write
+
+        Raises:
+            requests.HTTPError if OpenTSDB rejects the datapoints
+
+            requests.RequestException if OpenTSDB still can't be reached
+            after all retries
+        """
+        datapoints = self.container_list_to_datapoints(list_of_containers)
+        batch_size = self.batch_size
+        if batch_size <= 0:
+            batch_size = max(len(datapoints), 1)
+        for batch_start in range(0, len(datapoints), batch_size):
+            batch = datapoints[batch_start:batch_start + batch_size]
+
+            def put():
+                response = req.post(self.put_url, json=batch,
+                                    timeout=self.request_timeout)
+                response.raise_for_status()
+
+            self.retry_policy.run(put, is_transient_put_error)
+            self.write_count += 1
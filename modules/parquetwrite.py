@@ -0,0 +1,172 @@
+""" Contains classes and methods that write data to a Parquet file
+
+Instead of sending data to a time series database, measurements are saved
+to a single Parquet file with typed columns, for analysis in pandas or
+polars. Each measurement is a row with the time, measurement name, site,
+pollutant, value, unit and status, rather than one column per pollutant, so
+sites measuring different pollutants fit in the same table. It accepts the
+same list of containers as InfluxWriter
+
+    Classes:
+        ParquetWriter: Writes containers to a Parquet file
+
+"""
+
+__author__ = "Idris Hayward"
+__copyright__ = "2021, Idris Hayward"
+__credits__ = ["Idris Hayward"]
+__license__ = "GNU General Public License v3.0"
+__version__ = "1.0"
+__maintainer__ = "Idris Hayward"
+__email__ = "CaderIdrisGH@outlook.com"
+__status__ = "Stable Release"
+
+import datetime as dt
+import os
+
+import pyarrow as pa
+import pyarrow.parquet as pq
+
+from modules.aurn import is_measurement
+from modules.partition import (partition_container_list, partitioned_path,
+                               validate_partition)
+
+SCHEMA = pa.schema(
+        [
+            ("timestamp", pa.timestamp("us", tz="UTC")),
+            ("measurement", pa.string()),
+            ("site_code", pa.string()),
+            ("pollutant", pa.string()),
+            ("value", pa.float64()),
+            ("unit", pa.string()),
+            ("status", pa.string())
+        ]
+        )
+
+
+class ParquetWriter:
+    """ Writes containers to a Parquet file
+
+    All measurements from a run are written to the same file, each list of
+    containers as its own row group. The file is replaced when the class is
+    initialised and is only readable once close has been called, as the
+    Parquet footer is written last. If "File Partition" is Year or Month,
+    measurements are instead written to one file per year or month, named
+    by adding the partition to the path (e.g aurn_2019.parquet). Each
+    partitioned file is replaced the first time it is written to in a run
+
+    Attributes:
+        config (dict): Contains the path of the file to save to
+
+        path (str): Path of the Parquet file
+
+        partition (str): None, Year or Month, "File Partition" from config
+        (default None)
+
+        writers (dict): pyarrow.parquet.ParquetWriter for each file
+        written to this run, keyed by path
+
+        row_count (int): Number of rows written
+
+    Methods:
+        container_list_to_table: Converts list of containers to a table
+
+        write_container_list: Writes list of containers to the file, or
+        the file for each partition they are in
+
+        close: Writes the file footers and closes the files
+    """
+    def __init__(self, parquet_config):
+        """ Initialises class and opens the output file
+
+            Keyword arguments:
+                parquet_config (dict): The following keys are required:
+                    - "Parquet File": Path to save Parquet file to
+                The following keys are optional:
+                    - "File Partition": None (default), Year or Month
+
+            Raises:
+                ValueError if "File Partition" isn't None, Year or Month
+        """
+        self.config = parquet_config
+        self.path = self.config["Parquet File"]
+        self.partition = validate_partition(
+                self.config.get("File Partition", "None")
+                )
+        directory = os.path.dirname(self.path)
+        if directory != "":
+            os.makedirs(directory, exist_ok=True)
+        self.writers = dict()
+        if self.partition == "None":
+            self.writers[self.path] = pq.ParquetWriter(self.path, SCHEMA)
+        self.row_count = 0
+
+    def container_list_to_table(self, list_of_containers):
+        """ Converts list of containers to a table
+
+        Each numeric field that isn't a location field becomes a row. The
+        status is taken from the "{pollutant} status" tag and the unit from
+        the "{pollutant} unit" tag or field (see "Unit Placement"), each
+        None if the container doesn't have one. Times without a timezone
+        are treated as UTC
+
+        Keyword arguments:
+            list_of_containers (list): Containers in the format used by
+            InfluxWriter
+
+        Returns:
+            pyarrow Table with timestamp, measurement, site_code,
+            pollutant, value, unit and status columns
+        """
+        columns = {name: list() for name in SCHEMA.names}
+        for container in list_of_containers:
+            timestamp = container["time"]
+            if timestamp.tzinfo is None:
+                timestamp = timestamp.replace(tzinfo=dt.timezone.utc)
+            site_code = container["tags"].get("Download Code")
+            for field, value in container["fields"].items():
+                if not is_measurement(field, value):
+                    continue
+                columns["timestamp"].append(timestamp)
+                columns["measurement"].append(container["measurement"])
+                columns["site_code"].append(
+                        None if site_code is None else str(site_code)
+                        )
+                columns["pollutant"].append(field)
+                columns["value"].append(float(value))
+                columns["unit"].append(
+                        container["tags"].get(
+                            f"{field} unit",
+                            container["fields"].get(f"{field} unit")
+                            )
+                        )
+                columns["status"].append(
+                        container["tags"].get(f"{field} status")
+                        )
+        return pa.table(columns, schema=SCHEMA)
+
+    def write_container_list(self, list_of_containers):
+        """ Writes list of containers to the file, or the file for each
+        partition they are in, as a row group
+
+        Keyword arguments:
+            list_of_containers (list): Containers to write
+        """
+        partitions = partition_container_list(
+                list_of_containers, self.partition
+                )
+        for name, containers in partitions.items():
+            table = self.container_list_to_table(containers)
+            if table.num_rows == 0:
+                continue
+            path = partitioned_path(self.path, name)
+            if path not in self.writers:
+                self.writers[path] = pq.ParquetWriter(path, SCHEMA)
+            self.writers[path].write_table(table)
+            self.row_count += table.num_rows
+
+    def close(self):
+        """ Writes the file footers and closes the files
+        """
+        for writer in self.writers.values():
+            writer.close()
@@ -0,0 +1,132 @@
+""" Contains functions that split measurements in to separate files by year
+or month
+
+Exports covering decades of measurements make very large files when
+written to a single file. File based exporters can instead write each
+year (or month) of measurements to its own file, named after the period
+it covers. Containers are partitioned by the hour they measure rather than
+the hour they end, so midnight on the 1st of January is in the previous
+year's file along with the rest of that year's measurements. Aggregates
+are stamped at the start of the day or month they cover instead, and mark
+this with "period_start" so they are partitioned by their time unchanged
+
+    Functions:
+        validate_partition: Checks a partition mode from config is usable
+
+        partition_name: Name of the partition a measurement belongs to
+
+        partition_container_list: Splits a list of containers by partition
+
+        partitioned_path: Adds a partition name to a file path
+
+"""
+
+__author__ = "Idris Hayward"
+__copyright__ = "2021, Idris Hayward"
+__credits__ = ["Idris Hayward"]
+__license__ = "GNU General Public License v3.0"
+__version__ = "1.0"
+__maintainer__ = "Idris Hayward"
+__email__ = "CaderIdrisGH@outlook.com"
+__status__ = "Stable Release"
+
+import datetime as dt
+import os
+
+PARTITIONS = [
+        "None",
+        "Year",
+        "Month"
+        ]
+
+
+def validate_partition(partition):
+    """ Checks a partition mode from config is usable
+
+    Keyword arguments:
+        partition (str): None, Year or Month
+
+    Returns:
+        partition unchanged
+
+    Raises:
+        ValueError if partition isn't None, Year or Month
+    """
+    if partition not in PARTITIONS:
+        raise ValueError(
+            f'"{partition}" is not a valid file partition, use None, Year '
+            f"or Month"
+        )
+    return partition
+
+
+def partition_name(timestamp, partition, period_start=False):
+    """ Name of the partition a measurement belongs to
+
+    Keyword arguments:
+        timestamp (datetime): Time of the measurement, the end of the hour
+        measured
+
+        partition (str): Year or Month
+
+        period_start (bool): The timestamp is the start of the period
+        measured (e.g a daily aggregate) rather than the end of the hour
+
+    Returns:
+        Year (e.g 2019) or year and month (e.g 2019-01) as str
+    """
+    if not period_start:
+        timestamp = timestamp - dt.timedelta(hours=1)
+    if partition == "Month":
+        return f"{timestamp.year}-{timestamp.month:02d}"
+    return str(timestamp.year)
+
+
+def partition_container_list(list_of_containers, partition):
+    """ Splits a list of containers by partition
+
+    Keyword arguments:
+        list_of_containers (list): Containers in the format used by
+        InfluxWriter. Containers with "period_start" set to True are
+        partitioned by their time unchanged
+
+        partition (str): None, Year or Month. None keeps all containers
+        together
+
+    Returns:
+        Dict of lists of containers keyed by partition name, in the order
+        the partitions were first seen. The key is None if partition is
+        None
+    """
+    partitions = dict()
+    for container in list_of_containers:
+        if partition == "None":
+            name = None
+        else:
+            name = partition_name(
+                    container["time"], partition,
+                    container.get("period_start", False)
+                    )
+        partitions.setdefault(name, list()).append(container)
+    return partitions
+
+
+def partitioned_path(path, name):
+    """ Adds a partition name to a file path
+
+    The name is added before the file extension, so aurn.lp.gz becomes
+    aurn_2019.lp.gz
+
+    Keyword arguments:
+        path (str): Path of the file
+
+        name (str): Partition name, None leaves the path unchanged
+
+    Returns:
+        Path including the partition name
+    """
+    if name is None:
+        return path
+    directory, filename = os.path.split(path)
+    stem, dot, extension = filename.partition(".")
+    return os.path.join(directory, f"{stem}_{name}{dot}{extension}")
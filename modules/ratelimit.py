@@ -0,0 +1,87 @@
+""" Contains classes that limit the rate requests are sent to a website
+
+The DEFRA website is queried once per site for metadata and once per site
+per year for measurements. A single RateLimiter is shared by everything
+that sends requests so the total rate never goes above the configured
+limit, no matter how many threads are sending requests
+
+    Classes:
+        RateLimiter: Thread safe token bucket limiting requests per second
+
+"""
+
+__author__ = "Idris Hayward"
+__copyright__ = "2021, Idris Hayward"
+__credits__ = ["Idris Hayward"]
+__license__ = "GNU General Public License v3.0"
+__version__ = "1.0"
+__maintainer__ = "Idris Hayward"
+__email__ = "CaderIdrisGH@outlook.com"
+__status__ = "Stable Release"
+
+import threading
+import time
+
+
+class RateLimiter:
+    """ Thread safe token bucket limiting requests per second
+
+    Tokens are added to the bucket at a fixed rate, up to a maximum of
+    'burst'. Each request takes a token, waiting until one is available
+    if the bucket is empty
+
+    Attributes:
+        rate (float): Tokens added per second, 0 or less disables the
+        limiter
+
+        burst (float): Maximum number of tokens in the bucket
+
+        tokens (float): Tokens currently in the bucket
+
+        last_refill (float): Monotonic time the bucket was last refilled
+
+        lock (threading.Lock): Makes the limiter safe to share between
+        threads
+
+    Methods:
+        wait: Blocks until a request is allowed to be sent
+    """
+    def __init__(self, rate, burst=1):
+        """ Initialises class
+
+        Keyword arguments:
+            rate (float): Maximum requests per second, 0 or less disables
+            the limiter
+
+            burst (float): Maximum number of requests that can be sent
+            at once after a quiet period
+        """
+        self.rate = float(rate)
+        self.burst = max(float(burst), 1)
+        self.tokens = self.burst
+        self.last_refill = time.monotonic()
+        self.lock = threading.Lock()
+
+    def wait(self):
+        """ Blocks until a request is allowed to be sent
+
+        Variables:
+            now (float): Current monotonic time
+
+            sleep_time (float): Time until the next token is added
+        """
+        if self.rate <= 0:
+            return
+        while True:
+            with self.lock:
+                now = time.monotonic()
+                self.tokens = min(
+                        self.burst,
+                        self.tokens + (now - self.last_refill) * self.rate
+                        )
+                self.last_refill = now
+                if self.tokens >= 1:
+                    self.tokens -= 1
+                    return
+                sleep_time = (1 - self.tokens) / self.rate
+            time.sleep(sleep_time)
@@ -0,0 +1,103 @@
+""" Contains classes used to retry requests that fail for transient reasons
+
+Network blips, timeouts and server errors usually clear up if the request
+is sent again after a short wait. RetryPolicy holds how many times to retry
+and how long to wait between attempts so the same behaviour can be used
+wherever requests are sent
+
+    Classes:
+        RetryPolicy: Number of retries and exponential backoff with jitter
+
+"""
+
+__author__ = "Idris Hayward"
+__copyright__ = "2021, Idris Hayward"
+__credits__ = ["Idris Hayward"]
+__license__ = "GNU General Public License v3.0"
+__version__ = "1.0"
+__maintainer__ = "Idris Hayward"
+__email__ = "CaderIdrisGH@outlook.com"
+__status__ = "Stable Release"
+
+import random
+import threading
+import time
+
+
+class RetryPolicy:
+    """ Number of retries and exponential backoff with jitter
+
+    Attributes:
+        retries (int): Maximum number of times to retry a failed request
+
+        backoff (float): Seconds to wait before the first retry, doubled
+        for every retry after
+
+        jitter (float): Maximum random seconds added to each wait so
+        multiple clients don't retry at the same time
+
+        retry_count (int): Total number of retries made using this policy
+
+        count_lock (threading.Lock): Guards retry_count, as the same policy
+        is used by the metadata and download worker threads
+
+    Methods:
+        delay: Seconds to wait before a retry
+
+        run: Call a function, retrying it if it raises a retryable error
+    """
+    def __init__(self, retries=3, backoff=1, jitter=0.5):
+        """ Initialises class
+
+        Keyword arguments:
+            retries (int): Maximum number of times to retry a failed
+            request
+
+            backoff (float): Seconds to wait before the first retry
+
+            jitter (float): Maximum random seconds added to each wait
+        """
+        self.retries = int(retries)
+        self.backoff = float(backoff)
+        self.jitter = float(jitter)
+        self.retry_count = 0
+        self.count_lock = threading.Lock()
+
+    def delay(self, attempt):
+        """ Seconds to wait before a retry
+
+        Keyword arguments:
+            attempt (int): Number of the retry, starting at 0
+
+        Returns:
+            Seconds to wait
+        """
+        return (self.backoff * (2 ** attempt)) + random.uniform(0, self.jitter)
+
+    def run(self, function, is_retryable):
+        """ Call a function, retrying it if it raises a retryable error
+
+        Keyword arguments:
+            function (callable): Function with no arguments to call
+
+            is_retryable (callable): Takes a raised exception and returns
+            True if the call should be retried
+
+        Returns:
+            Whatever function returns
+
+        Raises:
+            Whatever function raises if the error isn't retryable or it
+            still fails after all retries
+        """
+        attempt = 0
+        while True:
+            try:
+                return function()
+            except Exception as error:
+                if attempt >= self.retries or not is_retryable(error):
+                    raise
+                time.sleep(self.delay(attempt))
+                attempt += 1
+                with self.count_lock:
+                    self.retry_count += 1
@@ -0,0 +1,116 @@
+""" Contains functions that apply custom corrections to measurements before
+export
+
+Some users need to correct measurements (unit fixes, site specific
+calibrations etc) before they are exported. Rather than editing the
+exporter, a transform function can be named in the config. It is called
+with every container before it is written and can change its tags and
+fields in place. Setting a field to None removes it, containers left with
+no measurements aren't exported
+
+    Functions:
+        load_transform: Imports a transform function from a module
+
+        apply_transform: Applies a transform function to a list of
+        containers
+
+        clamp_negatives: Example transform that sets negative measurements
+        to 0
+
+"""
+
+__author__ = "Idris Hayward"
+__copyright__ = "2021, Idris Hayward"
+__credits__ = ["Idris Hayward"]
+__license__ = "GNU General Public License v3.0"
+__version__ = "1.0"
+__maintainer__ = "Idris Hayward"
+__email__ = "CaderIdrisGH@outlook.com"
+__status__ = "Stable Release"
+
+import importlib
+
+from modules.aurn import is_measurement
+
+
+def load_transform(transform_path):
+    """ Imports a transform function from a module
+
+    Keyword arguments:
+        transform_path (str): Module and function name separated by a
+        colon, e.g modules.transform:clamp_negatives
+
+    Returns:
+        The transform function
+
+    Raises:
+        ValueError if transform_path isn't in module:function format or the
+        function can't be found
+    """
+    module_name, _, function_name = transform_path.partition(":")
+    if module_name == "" or function_name == "":
+        raise ValueError(
+            f'"{transform_path}" is not a valid transform, it must be in '
+            f"module:function format e.g modules.transform:clamp_negatives"
+        )
+    try:
+        transform = getattr(
+                importlib.import_module(module_name), function_name
+                )
+    except (ImportError, AttributeError):
+        raise ValueError(
+            f"Transform {function_name} could not be found in {module_name}"
+        )
+    if not callable(transform):
+        raise ValueError(f"Transform {transform_path} is not a function")
+    return transform
+
+
+def apply_transform(list_of_containers, transform):
+    """ Applies a transform function to a list of containers
+
+    The transform is called with each container and changes it in place,
+    its return value is ignored. Fields set to None are then removed and
+    containers with no measurements left are dropped
+
+    Keyword arguments:
+        list_of_containers (list): Containers in the format used by
+        InfluxWriter
+
+        transform (callable): Takes a container and changes it in place
+
+    Returns:
+        List of transformed containers
+    """
+    transformed_containers = list()
+    for container in list_of_containers:
+        container = container.copy()
+        container["tags"] = container["tags"].copy()
+        container["fields"] = container["fields"].copy()
+        transform(container)
+        container["fields"] = {
+                field: value for field, value in container["fields"].items()
+                if value is not None
+                }
+        has_measurement = any(
+                is_measurement(field, value)
+                for field, value in container["fields"].items()
+                )
+        if has_measurement:
+            transformed_containers.append(container)
+    return transformed_containers
+
+
+def clamp_negatives(container):
+    """ Example transform that sets negative measurements to 0
+
+    Instrument noise can give small negative concentrations, which some
+    tools can't handle. Location fields (e.g Easting) aren't changed
+
+    Keyword arguments:
+        container (dict): Container to change in place
+    """
+    for field, value in container["fields"].items():
+        if (is_measurement(field, value) and
+                isinstance(value, float) and value < 0):
+            container["fields"][field] = 0.0
@@ -0,0 +1,7 @@
+""" Tests for the AURN exporter
+
+Run from the root of the repository with python3 -m unittest discover tests.
+No requests are sent to the DEFRA website, pages and csvs are served from
+tests/fixtures by FakeSession in tests/fakes.py
+
+"""
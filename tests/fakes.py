@@ -0,0 +1,235 @@
+""" Fake requests session used to test AURNAPI without sending requests to
+the DEFRA website
+
+AURNAPI sends every request through the requests.Session it is given.
+FakeSession takes its place, serving fixture files (or any response) for
+known urls and a 404 for everything else, so scraping and parsing can be
+tested offline and always give the same result
+
+    Classes:
+        FakeResponse: Stands in for a requests.Response
+
+        FakeSession: Stands in for a requests.Session, serving responses
+        for known urls
+
+    Functions:
+        fixture_path: Path of a file in tests/fixtures
+
+        read_fixture: Reads a file in tests/fixtures
+
+        fake_config: Default config with waits turned off for tests
+
+"""
+
+__author__ = "Idris Hayward"
+__copyright__ = "2021, Idris Hayward"
+__credits__ = ["Idris Hayward"]
+__license__ = "GNU General Public License v3.0"
+__version__ = "1.0"
+__maintainer__ = "Idris Hayward"
+__email__ = "CaderIdrisGH@outlook.com"
+__status__ = "Stable Release"
+
+import http
+import io
+import json
+import os
+
+FIXTURE_DIRECTORY = os.path.join(os.path.dirname(__file__), "fixtures")
+
+CONFIG_PATH = os.path.join(
+        os.path.dirname(os.path.dirname(__file__)), "Settings", "config.json"
+        )
+
+# Urls of the DEFRA pages with the default config
+DOMAIN = "https://uk-air.defra.gov.uk"
+METADATA_CSV_URL = f"{DOMAIN}/openair/metadata.csv"
+
+
+def fixture_path(name):
+    """ Path of a file in tests/fixtures
+
+    Keyword arguments:
+        name (str): Name of the fixture file
+
+    Returns:
+        Path as str
+    """
+    return os.path.join(FIXTURE_DIRECTORY, name)
+
+
+def read_fixture(name):
+    """ Reads a file in tests/fixtures
+
+    Keyword arguments:
+        name (str): Name of the fixture file
+
+    Returns:
+        Contents of the file as bytes
+    """
+    with open(fixture_path(name), "rb") as fixture_file:
+        return fixture_file.read()
+
+
+def fake_config(overrides=None):
+    """ Default config with waits turned off for tests
+
+    Settings/config.json is used as a base so tests follow the shipped
+    defaults. The rate limiter, retry backoff and rate limit backoff are
+    turned off so failed requests are retried straight away
+
+    Keyword arguments:
+        overrides (dict): Config keys to change
+
+    Returns:
+        Config as dict
+    """
+    with open(CONFIG_PATH, "r") as config_file:
+        config = json.load(config_file)
+    config.update(
+            {
+                "Requests Per Second": 0,
+                "Request Retry Backoff": 0,
+                "Rate Limit Backoff": 0,
+                "Show Progress": False
+            }
+            )
+    if overrides is not None:
+        config.update(overrides)
+    return config
+
+
+class FakeResponse:
+    """ Stands in for a requests.Response
+
+    Only the attributes and methods AURNAPI uses are provided. raw gives a
+    new stream of the body each time, so the same response can be served
+    more than once
+
+    Attributes:
+        content (bytes): Body of the response
+
+        status_code (int): HTTP status code
+
+        headers (dict): Response headers
+
+        reason (str): Reason given with the status code
+
+        encoding (str): Always None, the body is decoded as utf-8
+
+        closed (bool): Has close been called?
+
+    Methods:
+        raw: Stream of the body, as read by pandas
+
+        close: Records the response was closed
+    """
+    def __init__(self, content=b"", status_code=200, headers=None):
+        """ Initialises class
+
+        Keyword arguments:
+            content (bytes): Body of the response
+
+            status_code (int): HTTP status code
+
+            headers (dict): Response headers
+        """
+        self.content = content
+        self.status_code = status_code
+        self.headers = headers if headers is not None else dict()
+        self.reason = http.HTTPStatus(status_code).phrase
+        self.encoding = None
+        self.closed = False
+
+    @property
+    def ok(self):
+        """ Is the status code below 400, as in requests?
+        """
+        return self.status_code < 400
+
+    @property
+    def raw(self):
+        """ Stream of the body, as read by pandas
+        """
+        return io.BytesIO(self.content)
+
+    def close(self):
+        """ Records the response was closed, there is no connection to
+        close
+        """
+        self.closed = True
+
+
+class FakeSession:
+    """ Stands in for a requests.Session, serving responses for known urls
+
+    Routes map a url to a fixture file name, a FakeResponse, an exception
+    to raise or a list of these. Lists are served in order, the last being
+    repeated once the others have been served. Urls without a route get a
+    404, the same as a missing measurement csv
+
+    Attributes:
+        routes (dict): Responses for each url
+
+        headers (dict): Headers set by AURNAPI
+
+        requests (list): Every url requested, in order
+
+    Methods:
+        add: Adds responses for a url
+
+        get: Serves the next response for a url
+    """
+    def __init__(self, routes=None):
+        """ Initialises class
+
+        Keyword arguments:
+            routes (dict): Responses for each url, see add
+        """
+        self.routes = dict()
+        self.headers = dict()
+        self.requests = list()
+        for url, responses in (routes or dict()).items():
+            self.add(url, responses)
+
+    def add(self, url, responses):
+        """ Adds responses for a url
+
+        Keyword arguments:
+            url (str): Url to respond to
+
+            responses: Fixture file name, FakeResponse, exception or a list
+            of them to serve in order
+        """
+        if not isinstance(responses, list):
+            responses = [responses]
+        self.routes[url] = [
+                FakeResponse(read_fixture(response))
+                if isinstance(response, str) else response
+                for response in responses
+                ]
+
+    def get(self, url, stream=False, timeout=None):
+        """ Serves the next response for a url
+
+        Keyword arguments:
+            url (str): Url requested
+
+            stream (bool): Ignored, the body is always available
+
+            timeout (float): Ignored, fake responses are instant
+
+        Returns:
+            FakeResponse for the url, a 404 if it has no route
+
+        Raises:
+            The exception routed to the url, if one was
+        """
+        self.requests.append(url)
+        responses = self.routes.get(url)
+        if responses is None:
+            return FakeResponse(b"Not Found", 404)
+        response = responses.pop(0) if len(responses) > 1 else responses[0]
+        if isinstance(response, Exception):
+            raise response
+        return response
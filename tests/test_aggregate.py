@@ -0,0 +1,218 @@
+""" Tests for modules/aggregate.py
+
+"""
+
+import datetime as dt
+import unittest
+
+from modules.aggregate import SiteStatistics, aggregate_container_list
+
+
+def hourly_containers(start, values):
+    """ Hourly containers for Aberdeen
+
+    Keyword arguments:
+        start (datetime): Start of the first hour
+
+        values (dict): Measurements keyed by pollutant, each a dict of
+        values keyed by the number of hours after start the hour ends.
+        Hours without a value have a container without the pollutant,
+        as missing measurements do
+    """
+    hours = {hour for pollutant in values.values() for hour in pollutant}
+    containers = list()
+    for hour in range(1, max(hours) + 1):
+        container = {
+                "time": start + dt.timedelta(hours=hour),
+                "measurement": "Automatic Urban Rural Network",
+                "tags": {
+                    "Site Name": "Aberdeen",
+                    "Download Code": "ABD"
+                },
+                "fields": {"Latitude": 57.15736}
+                }
+        for pollutant, pollutant_values in values.items():
+            if hour in pollutant_values:
+                container["fields"][pollutant] = pollutant_values[hour]
+                container["tags"][f"{pollutant} status"] = "V"
+                container["tags"][f"{pollutant} unit"] = "ugm-3"
+                container["tags"][f"{pollutant} method"] = "Chemiluminescence"
+        containers.append(container)
+    return containers
+
+
+class TestDailyAggregates(unittest.TestCase):
+    """ Aggregating hourly containers to daily means and maxima
+    """
+    def setUp(self):
+        # Nitrogen dioxide is missing from 01:00 to 06:00, leaving 18 of
+        # 24 hours. Ozone is only measured from 01:00 to 17:00. The hour
+        # ending at midnight belongs to the day before
+        self.containers = hourly_containers(
+                dt.datetime(2019, 1, 1),
+                {
+                    "Nitrogen dioxide": {
+                        hour: float(hour) for hour in range(7, 25)
+                        },
+                    "Ozone": {hour: 1.0 for hour in range(1, 18)}
+                }
+                )
+
+    def test_daily(self):
+        aggregates = aggregate_container_list(self.containers)
+        self.assertEqual(len(aggregates), 1)
+        aggregate = aggregates[0]
+        self.assertEqual(aggregate["time"], dt.datetime(2019, 1, 1))
+        self.assertEqual(aggregate["measurement"], "air_quality_daily")
+        self.assertTrue(aggregate["period_start"])
+        self.assertEqual(
+                aggregate["fields"],
+                {
+                    "Nitrogen dioxide mean": 15.5,
+                    "Nitrogen dioxide max": 24.0,
+                    "Nitrogen dioxide capture": 0.75,
+                    "Latitude": 57.15736
+                }
+                )
+
+    def test_min_capture(self):
+        # 17 of 24 hours of Ozone is below the default 75%
+        aggregate = aggregate_container_list(
+                self.containers, min_capture=0.7
+                )[0]
+        self.assertEqual(aggregate["fields"]["Ozone mean"], 1.0)
+        self.assertAlmostEqual(aggregate["fields"]["Ozone capture"], 17 / 24)
+        aggregates = aggregate_container_list(
+                self.containers, min_capture=0.8
+                )
+        self.assertEqual(aggregates, list())
+
+    def test_tags(self):
+        # Status, unit and method tags describe single hours
+        aggregate = aggregate_container_list(self.containers)[0]
+        self.assertEqual(
+                aggregate["tags"],
+                {"Site Name": "Aberdeen", "Download Code": "ABD"}
+                )
+
+    def test_method_tag_name(self):
+        aggregate = aggregate_container_list(
+                self.containers, method_tag="instrument"
+                )[0]
+        # Tags come from the first hour of the day
+        self.assertIn("Ozone method", aggregate["tags"])
+
+
+class TestMonthlyAggregates(unittest.TestCase):
+    """ Aggregating hourly containers to monthly means and maxima
+    """
+    # Hours in January 2019
+    hours = 31 * 24
+
+    def aggregate(self, missing_hours):
+        containers = hourly_containers(
+                dt.datetime(2019, 1, 1),
+                {
+                    "Nitrogen dioxide": {
+                        hour: 2.0 for hour in
+                        range(missing_hours + 1, self.hours + 1)
+                        }
+                }
+                )
+        return aggregate_container_list(
+                containers, period="monthly",
+                measurement="air_quality_monthly"
+                )
+
+    def test_monthly(self):
+        # Midnight on 1st February is the last hour of January
+        aggregates = self.aggregate(150)
+        self.assertEqual(len(aggregates), 1)
+        aggregate = aggregates[0]
+        self.assertEqual(aggregate["time"], dt.datetime(2019, 1, 1))
+        self.assertEqual(aggregate["measurement"], "air_quality_monthly")
+        self.assertEqual(aggregate["fields"]["Nitrogen dioxide mean"], 2.0)
+        self.assertEqual(aggregate["fields"]["Nitrogen dioxide capture"],
+                         (self.hours - 150) / self.hours)
+
+    def test_min_capture(self):
+        # 544 of 744 hours is below 75%
+        self.assertEqual(self.aggregate(200), list())
+
+
+class TestSiteStatistics(unittest.TestCase):
+    """ Minimum, maximum, mean and count of each pollutant at a site
+    """
+    def setUp(self):
+        self.statistics = SiteStatistics(
+                {
+                    "tags": {"Site Name": "Aberdeen", "Download Code": "ABD"},
+                    "fields": {"Latitude": 57.15736}
+                }
+                )
+        # Missing measurements aren't in the containers, added a year at
+        # a time as they are exported
+        self.statistics.add_container_list(
+                hourly_containers(
+                    dt.datetime(2019, 12, 31, 21),
+                    {
+                        "Nitrogen dioxide": {1: 4.0, 3: 10.0},
+                        "Ozone": {2: 3.0}
+                    }
+                    )
+                )
+        self.statistics.add_container_list(
+                hourly_containers(
+                    dt.datetime(2020, 1, 1),
+                    {"Nitrogen dioxide": {1: 1.0, 3: 5.0}}
+                    )
+                )
+
+    def test_as_dict(self):
+        self.assertEqual(
+                self.statistics.as_dict(),
+                {
+                    "Site Name": "Aberdeen",
+                    "Download Code": "ABD",
+                    "pollutants": {
+                        "Nitrogen dioxide": {
+                            "min": 1.0, "max": 10.0, "mean": 5.0, "count": 4
+                            },
+                        "Ozone": {
+                            "min": 3.0, "max": 3.0, "mean": 3.0, "count": 1
+                            }
+                    }
+                }
+                )
+
+    def test_as_container(self):
+        container = self.statistics.as_container()
+        self.assertEqual(container["time"], dt.datetime(2020, 1, 1, 3))
+        self.assertEqual(container["measurement"], "site_stats")
+        self.assertEqual(
+                container["tags"],
+                {"Site Name": "Aberdeen", "Download Code": "ABD"}
+                )
+        self.assertEqual(container["fields"]["Nitrogen dioxide mean"], 5.0)
+        self.assertEqual(container["fields"]["Nitrogen dioxide count"], 4.0)
+        self.assertEqual(container["fields"]["Ozone min"], 3.0)
+        self.assertNotIn("Latitude min", container["fields"])
+
+    def test_nothing_added(self):
+        statistics = SiteStatistics(self.statistics.metadata)
+        statistics.add_container_list(
+                [
+                    {
+                        "time": dt.datetime(2019, 1, 1, 1),
+                        "measurement": "Automatic Urban Rural Network",
+                        "tags": {"Download Code": "ABD"},
+                        "fields": {"Latitude": 57.15736, "local_time": "01:00"}
+                    }
+                ]
+                )
+        self.assertIsNone(statistics.as_container())
+        self.assertEqual(statistics.as_dict()["pollutants"], dict())
+
+
+if __name__ == "__main__":
+    unittest.main()
@@ -0,0 +1,195 @@
+""" Tests for modules/annotatedcsv.py
+
+"""
+
+import csv
+import datetime as dt
+import os
+import tempfile
+import unittest
+
+from modules.annotatedcsv import (
+        AnnotatedCSVWriter,
+        format_timestamp,
+        validate_timestamp_format
+        )
+
+
+def container(time, value=10.0):
+    """ Container with a numeric and a string field at time
+    """
+    return {
+            "time": time,
+            "measurement": "Automatic Urban Rural Network",
+            "tags": {"Site Name": "Aberdeen", "Download Code": "ABD"},
+            "fields": {"Nitrogen dioxide": value, "local_time": "01:00"}
+            }
+
+
+def read_rows(path):
+    """ Reads every row of a csv
+    """
+    with open(path, "r", newline="") as csv_file:
+        return list(csv.reader(csv_file))
+
+
+class TestFormatTimestamp(unittest.TestCase):
+    """ Formatting the time column
+    """
+    def test_rfc3339(self):
+        self.assertEqual(
+                format_timestamp(dt.datetime(2019, 1, 1, 1)),
+                "2019-01-01T01:00:00Z"
+                )
+
+    def test_epoch(self):
+        # influx write reads dateTime:number as nanoseconds
+        self.assertEqual(
+                format_timestamp(dt.datetime(2019, 1, 1, 1), "Epoch"),
+                "1546304400000000000"
+                )
+
+    def test_timezone(self):
+        self.assertEqual(
+                format_timestamp(
+                    dt.datetime(2019, 7, 1, 2, tzinfo=dt.timezone(
+                        dt.timedelta(hours=1)
+                        ))
+                    ),
+                "2019-07-01T01:00:00Z"
+                )
+
+    def test_strftime(self):
+        self.assertEqual(
+                format_timestamp(dt.datetime(2019, 1, 1, 1), "%Y-%m-%d %H"),
+                "2019-01-01 01"
+                )
+
+    def test_invalid_format(self):
+        with self.assertRaises(ValueError):
+            validate_timestamp_format("Unix")
+
+
+class TestAnnotatedCSVWriter(unittest.TestCase):
+    """ Writing annotated csvs
+    """
+    def setUp(self):
+        self.directory = tempfile.TemporaryDirectory()
+
+    def tearDown(self):
+        self.directory.cleanup()
+
+    def writer(self, overrides=None):
+        config = {"Annotated CSV Directory": self.directory.name}
+        config.update(overrides or dict())
+        return AnnotatedCSVWriter(config)
+
+    def path(self, file_name):
+        return os.path.join(self.directory.name, file_name)
+
+    def test_header_rows(self):
+        # The annotation name shares a cell with the first column's
+        # annotation, as influx write expects
+        self.writer().write_container_list(
+                [container(dt.datetime(2019, 1, 1, 1))]
+                )
+        rows = read_rows(
+                self.path("Automatic_Urban_Rural_Network_ABD.csv")
+                )
+        self.assertEqual(
+                rows[0],
+                ["#datatype measurement", "tag", "tag", "double", "string",
+                 "dateTime:RFC3339"]
+                )
+        self.assertEqual(
+                rows[1],
+                ["#group false", "false", "false", "false", "false", "false"]
+                )
+        self.assertEqual(rows[2], ["#default", "", "", "", "", ""])
+        self.assertEqual(
+                rows[3],
+                ["measurement", "Site Name", "Download Code",
+                 "Nitrogen dioxide", "local_time", "time"]
+                )
+        self.assertEqual(
+                rows[4],
+                ["Automatic Urban Rural Network", "Aberdeen", "ABD", "10.0",
+                 "01:00", "2019-01-01T01:00:00Z"]
+                )
+        self.assertEqual(len(rows), 5)
+
+    def test_union_of_columns(self):
+        # Containers with different tags and fields share one header, the
+        # missing cells are left blank
+        second_container = container(dt.datetime(2019, 1, 1, 2))
+        second_container["tags"]["Nitrogen dioxide status"] = "V"
+        del second_container["fields"]["local_time"]
+        rows = self.writer().container_list_to_rows(
+                [container(dt.datetime(2019, 1, 1, 1)), second_container]
+                )
+        self.assertEqual(
+                rows[3],
+                ["measurement", "Site Name", "Download Code",
+                 "Nitrogen dioxide status", "Nitrogen dioxide", "local_time",
+                 "time"]
+                )
+        self.assertEqual(rows[4][3], "")
+        self.assertEqual(rows[5][5], "")
+
+    def test_epoch_datatype(self):
+        rows = self.writer({"Timestamp Format": "Epoch"}).\
+            container_list_to_rows([container(dt.datetime(2019, 1, 1, 1))])
+        self.assertEqual(rows[0][-1], "dateTime:number")
+        self.assertEqual(rows[-1][-1], "1546304400000000000")
+
+    def test_one_file_per_site(self):
+        # Each year is added to the same file as its own table
+        writer = self.writer()
+        writer.write_container_list([container(dt.datetime(2019, 1, 1, 1))])
+        writer.write_container_list([container(dt.datetime(2020, 1, 1, 1))])
+        rows = read_rows(
+                self.path("Automatic_Urban_Rural_Network_ABD.csv")
+                )
+        self.assertEqual(len(rows), 11)
+        self.assertEqual(rows[5], [])
+        self.assertEqual(rows[6][0], "#datatype measurement")
+        self.assertEqual(rows[-1][-1], "2020-01-01T01:00:00Z")
+
+    def test_emptied_on_start(self):
+        self.writer().write_container_list(
+                [container(dt.datetime(2019, 1, 1, 1))]
+                )
+        self.writer().write_container_list(
+                [container(dt.datetime(2019, 1, 1, 2))]
+                )
+        rows = read_rows(
+                self.path("Automatic_Urban_Rural_Network_ABD.csv")
+                )
+        self.assertEqual(len(rows), 5)
+
+    def test_partitioned(self):
+        # Midnight on 1st February is the last hour of January
+        writer = self.writer({"File Partition": "Month"})
+        writer.write_container_list(
+                [
+                    container(dt.datetime(2019, 1, 31, 23)),
+                    container(dt.datetime(2019, 2, 1)),
+                    container(dt.datetime(2019, 2, 1, 1))
+                ]
+                )
+        self.assertEqual(
+                len(read_rows(self.path(
+                    "Automatic_Urban_Rural_Network_ABD_2019-01.csv"
+                    ))),
+                6
+                )
+        self.assertEqual(
+                len(read_rows(self.path(
+                    "Automatic_Urban_Rural_Network_ABD_2019-02.csv"
+                    ))),
+                5
+                )
+
+
+if __name__ == "__main__":
+    unittest.main()
@@ -0,0 +1,1061 @@
+""" Tests for modules/aurn.py
+
+Metadata pages, the metadata csv and measurement csvs are served from
+tests/fixtures by FakeSession, so no requests are sent to DEFRA
+
+"""
+
+import datetime as dt
+import gzip
+import os
+import time
+import unittest
+
+from modules.aurn import (
+        AURNAPI,
+        DEFAULT_MISSING_SENTINELS,
+        AURNRateLimitedError,
+        count_measurements,
+        decode_entities,
+        response_content,
+        retry_after_seconds,
+        validate_year
+        )
+from modules.lineprotocol import timestamp_nanoseconds
+from modules.opentsdbwrite import OpenTSDBWriter
+from tests.fakes import (
+        DOMAIN,
+        METADATA_CSV_URL,
+        FakeResponse,
+        FakeSession,
+        fake_config,
+        read_fixture
+        )
+
+# Metadata for Aberdeen, as added to metadata by get_metadata
+STATION = {
+        "tags": {
+            "Site Name": "Aberdeen",
+            "Download Code": "ABD"
+        },
+        "fields": {
+            "Latitude": 57.15736
+        }
+    }
+
+
+def csv_url(download_code, year):
+    """ Url of a measurement csv with the default config
+    """
+    return f"{DOMAIN}/data_files/site_data/{download_code}_{year}.csv"
+
+
+def site_info_url(config, uk_air_id):
+    """ Url of a site info page with the default config
+    """
+    return (f"{DOMAIN}{config['AURN Site Info']}{uk_air_id}"
+            f"{config['AURN Site Info Provider']}")
+
+
+def measurement_api(overrides=None, years=(2019,)):
+    """ AURNAPI with the Aberdeen measurement csvs downloaded for years
+    """
+    session = FakeSession(
+            {
+                csv_url("ABD", year): f"ABD_{year}.csv" for year in years
+            }
+            )
+    aurn = AURNAPI(fake_config(overrides), session=session)
+    for year in years:
+        aurn.get_csv_measurements("ABD", year)
+    return aurn
+
+
+def convert(overrides=None, start_date=None, end_date=None, year=2019):
+    """ AURNAPI with an Aberdeen measurement csv converted to containers
+    """
+    aurn = measurement_api(overrides, years=(year,))
+    aurn.csv_to_json_list(STATION, "ABD", year, start_date, end_date)
+    return aurn
+
+
+def containers_by_time(aurn, year=2019):
+    """ Containers converted from a csv, keyed by their time
+    """
+    return {
+            container["time"]: container
+            for container in aurn.measurement_jsons[year]["ABD"]
+            }
+
+
+def metadata_api(search_page="find_sites.html", overrides=None,
+                 metadata_csv="metadata.csv"):
+    """ AURNAPI with the metadata search, metadata csv and site info pages
+    served from fixtures
+    """
+    config = fake_config(overrides)
+    session = FakeSession(
+            {
+                f"{DOMAIN}{config['AURN Metadata Search']}": search_page,
+                METADATA_CSV_URL: metadata_csv,
+                site_info_url(config, "UKA00399"):
+                    "site_info_UKA00399.html",
+                site_info_url(config, "UKA00001"):
+                    "site_info_UKA00001.html"
+            }
+            )
+    return AURNAPI(config, session=session), session
+
+
+def site_names(aurn):
+    """ Names of the sites in metadata
+    """
+    return [station["tags"]["Site Name"] for station in aurn.metadata]
+
+
+class TestZeroAsMissing(unittest.TestCase):
+    """ Treating zero measurements of listed pollutants as missing
+    """
+    def test_zero_kept_by_default(self):
+        aurn = convert()
+        container = containers_by_time(aurn)[dt.datetime(2019, 1, 1, 1)]
+        self.assertEqual(container["fields"]["Ozone"], 0.0)
+        self.assertEqual(aurn.dropped_zeros, 0)
+
+    def test_zero_as_missing(self):
+        aurn = convert({"Zero As Missing": ["Ozone"]})
+        container = containers_by_time(aurn)[dt.datetime(2019, 1, 1, 1)]
+        self.assertNotIn("Ozone", container["fields"])
+        self.assertEqual(container["fields"]["Nitric oxide"], 1.5)
+        self.assertEqual(aurn.dropped_zeros, 1)
+
+    def test_duplicate_columns(self):
+        # Brackets are ignored and the pollutant covers its duplicate
+        # column, which has a zero at 03:00
+        aurn = convert(
+                {"Zero As Missing": ["PM10 particulate matter (FIDAS)"]}
+                )
+        container = containers_by_time(aurn)[dt.datetime(2019, 1, 1, 3)]
+        self.assertNotIn("PM10 particulate matter 2", container["fields"])
+        self.assertEqual(aurn.dropped_zeros, 1)
+
+    def test_renamed_pollutant(self):
+        aurn = convert(
+                {
+                    "Pollutant Field Names": {"Ozone": "o3"},
+                    "Zero As Missing": ["o3"]
+                }
+                )
+        containers = containers_by_time(aurn)
+        self.assertNotIn(
+                "o3", containers[dt.datetime(2019, 1, 1, 1)]["fields"]
+                )
+        self.assertEqual(
+                containers[dt.datetime(2019, 1, 1, 2)]["fields"]["o3"], 20.0
+                )
+        self.assertEqual(aurn.dropped_zeros, 1)
+
+    def test_counts_agree(self):
+        # Zeros dropped from the containers aren't counted as downloaded
+        aurn = convert(
+                {"Zero As Missing": ["Ozone", "PM10 particulate matter"]}
+                )
+        self.assertEqual(
+                aurn.count_valid_measurements("ABD", 2019),
+                count_measurements(aurn.measurement_jsons[2019]["ABD"])
+                )
+
+
+class TestMissingSentinels(unittest.TestCase):
+    """ Skipping measurements marked as missing
+    """
+    def test_default_sentinels(self):
+        # Each hour from 02:00 to 06:00 has Nitric oxide missing, marked
+        # with a different sentinel
+        sentinel_hours = {
+                "No data": 2,
+                "-": 3,
+                "n/a": 4,
+                "": 5,
+                "nan": 6
+                }
+        containers = containers_by_time(convert())
+        for sentinel, hour in sentinel_hours.items():
+            with self.subTest(sentinel=sentinel):
+                self.assertIn(sentinel.lower(), DEFAULT_MISSING_SENTINELS)
+                fields = containers[dt.datetime(2019, 1, 1, hour)]["fields"]
+                self.assertNotIn("Nitric oxide", fields)
+                self.assertIn("Nitrogen dioxide", fields)
+
+    def test_custom_sentinel(self):
+        containers = containers_by_time(
+                convert({"Missing Data Sentinels": ["1.5"]})
+                )
+        self.assertNotIn(
+                "Nitric oxide",
+                containers[dt.datetime(2019, 1, 1, 1)]["fields"]
+                )
+
+
+class TestDuplicateColumns(unittest.TestCase):
+    """ Keeping columns that share a name once brackets are removed
+    """
+    def test_columns(self):
+        # Every pollutant's status and unit column is named "status" and
+        # "unit" in the csv, and the two PM10 instruments only differ by
+        # the bracketed instrument name
+        csv = measurement_api().measurement_csvs[2019]["ABD"]
+        self.assertEqual(
+                list(csv.columns),
+                [
+                    "Datetime",
+                    "Nitric oxide",
+                    "Nitric oxide status",
+                    "Nitric oxide unit",
+                    "Nitrogen dioxide",
+                    "Nitrogen dioxide status",
+                    "Nitrogen dioxide unit",
+                    "Ozone",
+                    "Ozone status",
+                    "Ozone unit",
+                    "PM10 particulate matter",
+                    "PM10 particulate matter status",
+                    "PM10 particulate matter unit",
+                    "PM10 particulate matter 2",
+                    "PM10 particulate matter 2 status",
+                    "PM10 particulate matter 2 unit"
+                ]
+                )
+
+    def test_both_measurements_kept(self):
+        fields = containers_by_time(convert())[dt.datetime(2019, 1, 1, 1)][
+                "fields"]
+        self.assertEqual(fields["PM10 particulate matter"], 12.0)
+        self.assertEqual(fields["PM10 particulate matter 2"], 11.0)
+
+
+class TestDateRange(unittest.TestCase):
+    """ Trimming measurements to the requested date range
+    """
+    def test_trimmed_at_both_ends(self):
+        # Both ends of the range are inclusive
+        aurn = convert(
+                start_date=dt.datetime(2019, 1, 1, 3),
+                end_date=dt.datetime(2019, 1, 1, 5)
+                )
+        self.assertEqual(
+                sorted(containers_by_time(aurn).keys()),
+                [dt.datetime(2019, 1, 1, hour) for hour in [3, 4, 5]]
+                )
+        self.assertEqual(aurn.out_of_range_points, 5)
+
+    def test_midnight_at_end_of_range(self):
+        # A range ending in 2019 ends at midnight on 1st January 2020,
+        # the last hour measured in 2019
+        aurn = convert(
+                start_date=dt.datetime(2019, 1, 1),
+                end_date=dt.datetime(2020, 1, 1)
+                )
+        self.assertIn(dt.datetime(2020, 1, 1), containers_by_time(aurn))
+        self.assertEqual(aurn.out_of_range_points, 0)
+
+    def test_trimmed_across_years(self):
+        # The range starts partway through the 2019 csv and ends on the
+        # row the 2019 and 2020 csvs share
+        aurn = measurement_api(years=(2019, 2020))
+        for year in [2019, 2020]:
+            aurn.csv_to_json_list(STATION, "ABD", year,
+                                  dt.datetime(2019, 1, 1, 7),
+                                  dt.datetime(2020, 1, 1))
+            aurn.remove_boundary_duplicates("ABD", year)
+        times = [
+                container["time"] for year in [2019, 2020]
+                for container in aurn.measurement_jsons[year]["ABD"]
+                ]
+        self.assertEqual(
+                times,
+                [dt.datetime(2019, 1, 1, 7), dt.datetime(2020, 1, 1)]
+                )
+
+
+class TestFarFutureEndDate(unittest.TestCase):
+    """ Treating placeholder end dates far in the future as still open
+    """
+    def test_open_site(self):
+        aurn, session = metadata_api()
+        aurn.get_metadata(2019, 2019)
+        self.assertIn("Future End", site_names(aurn))
+
+    def test_placeholder_end_date(self):
+        # Future End's 2099 end date is a placeholder, so it is treated as
+        # still open like Aberdeen, which has no end date. Neither can
+        # have data for 2050 yet
+        aurn, session = metadata_api()
+        aurn.get_metadata(2050, 2050)
+        self.assertEqual(site_names(aurn), list())
+
+    def test_end_date_within_horizon(self):
+        aurn, session = metadata_api(overrides={"End Date Horizon": 100})
+        aurn.get_metadata(2050, 2050)
+        self.assertEqual(site_names(aurn), ["Future End"])
+
+
+class TestMetadataCsvLink(unittest.TestCase):
+    """ Reporting a missing or empty link to the metadata csv
+    """
+    def test_no_link(self):
+        aurn, session = metadata_api("find_sites_no_link.html")
+        with self.assertRaisesRegex(ValueError, "No link to the metadata"):
+            aurn.get_metadata_table()
+
+    def test_empty_link(self):
+        aurn, session = metadata_api("find_sites_empty_href.html")
+        with self.assertRaisesRegex(ValueError, "has an empty href"):
+            aurn.get_metadata_table()
+        # The empty link isn't requested
+        self.assertNotIn(METADATA_CSV_URL, session.requests)
+
+    def test_link(self):
+        aurn, session = metadata_api()
+        self.assertEqual(len(aurn.get_metadata_table()), 5)
+        self.assertIn(METADATA_CSV_URL, session.requests)
+
+
+class TestMethodTag(unittest.TestCase):
+    """ Tagging measurements with the instrument/method from the csv
+    """
+    def test_method_tag(self):
+        tags = containers_by_time(convert())[dt.datetime(2019, 1, 1, 1)][
+                "tags"]
+        self.assertEqual(tags["PM10 particulate matter method"], "TEOM")
+        self.assertEqual(tags["PM10 particulate matter 2 method"], "FIDAS")
+
+    def test_no_method_information(self):
+        # Nitric oxide has no instrument in brackets, so has no tag rather
+        # than a blank one
+        for container in convert().measurement_jsons[2019]["ABD"]:
+            self.assertNotIn("Nitric oxide method", container["tags"])
+            for value in container["tags"].values():
+                self.assertNotIn(str(value).strip().lower(), ["", "nan"])
+
+    def test_missing_measurement(self):
+        # PM10 (TEOM) has no measurement at 02:00
+        tags = containers_by_time(convert())[dt.datetime(2019, 1, 1, 2)][
+                "tags"]
+        self.assertNotIn("PM10 particulate matter method", tags)
+        self.assertEqual(tags["PM10 particulate matter 2 method"], "FIDAS")
+
+    def test_renamed(self):
+        tags = containers_by_time(
+                convert({"Method Tag": "instrument"})
+                )[dt.datetime(2019, 1, 1, 1)]["tags"]
+        self.assertEqual(tags["PM10 particulate matter instrument"], "TEOM")
+        self.assertNotIn("PM10 particulate matter method", tags)
+
+    def test_disabled(self):
+        for container in convert({"Method Tag": ""}).measurement_jsons[
+                2019]["ABD"]:
+            self.assertFalse(
+                    any(tag.endswith(" method") for tag in container["tags"])
+                    )
+
+
+class TestOutOfRangePoints(unittest.TestCase):
+    """ Dropping or keeping measurements outside the date range
+    """
+    # Midnight on 1st January 2020 is the only row after this
+    end_date = dt.datetime(2019, 6, 30)
+
+    def test_dropped(self):
+        aurn = convert(start_date=dt.datetime(2019, 1, 1),
+                       end_date=self.end_date)
+        containers = containers_by_time(aurn)
+        self.assertNotIn(dt.datetime(2020, 1, 1), containers)
+        self.assertEqual(len(containers), 7)
+        self.assertEqual(aurn.out_of_range_points, 1)
+
+    def test_kept(self):
+        aurn = convert({"Drop Out Of Range Points": False},
+                       start_date=dt.datetime(2019, 1, 1),
+                       end_date=self.end_date)
+        containers = containers_by_time(aurn)
+        self.assertIn(dt.datetime(2020, 1, 1), containers)
+        self.assertEqual(len(containers), 8)
+        # Kept points are still counted
+        self.assertEqual(aurn.out_of_range_points, 1)
+
+    def test_counts_agree(self):
+        for drop in [True, False]:
+            with self.subTest(drop=drop):
+                aurn = convert({"Drop Out Of Range Points": drop},
+                               start_date=dt.datetime(2019, 1, 1),
+                               end_date=self.end_date)
+                self.assertEqual(
+                        aurn.count_valid_measurements(
+                            "ABD", 2019, dt.datetime(2019, 1, 1),
+                            self.end_date
+                            ),
+                        count_measurements(
+                            aurn.measurement_jsons[2019]["ABD"]
+                            )
+                        )
+
+
+class TestHTMLEntities(unittest.TestCase):
+    """ Decoding HTML entities in scraped links and site metadata
+    """
+    def test_entities(self):
+        # The link to the metadata csv has &amp; between its parameters
+        aurn, session = metadata_api(
+                "find_sites_entities.html",
+                metadata_csv="metadata_entities.csv"
+                )
+        session.add(f"{METADATA_CSV_URL}?networks=AURN&format=csv",
+                    "metadata_entities.csv")
+        aurn.get_metadata(2019, 2019)
+        self.assertIn(f"{METADATA_CSV_URL}?networks=AURN&format=csv",
+                      session.requests)
+        self.assertEqual(site_names(aurn), ["Brighton & Hove"])
+        self.assertEqual(aurn.metadata[0]["tags"]["Zone"],
+                         "Brighton & Hove’s Urban Area")
+
+    def test_decode_entities(self):
+        self.assertEqual(decode_entities("5&#176;C"), "5°C")
+        self.assertEqual(decode_entities("Brighton & Hove"),
+                         "Brighton & Hove")
+        self.assertEqual(decode_entities(20.0), 20.0)
+
+
+class TestBlankRows(unittest.TestCase):
+    """ Dropping blank rows and stray whitespace from measurement csvs
+    """
+    def test_crlf_and_blank_lines(self):
+        # The 2019 fixture has CRLF line endings, a row of commas and a
+        # blank line at the end
+        csv = measurement_api().measurement_csvs[2019]["ABD"]
+        self.assertEqual(len(csv), 8)
+        self.assertFalse(any("\r" in column for column in csv.columns))
+        self.assertEqual(csv["Datetime"].isna().sum(), 0)
+
+    def test_lf(self):
+        csv = measurement_api(years=(2020,)).measurement_csvs[2020]["ABD"]
+        self.assertEqual(len(csv), 2)
+
+
+class TestProvenanceFields(unittest.TestCase):
+    """ Adding the source csv year and download time as fields
+    """
+    def test_off_by_default(self):
+        for container in convert().measurement_jsons[2019]["ABD"]:
+            self.assertNotIn("source_year", container["fields"])
+            self.assertNotIn("scraped_at", container["fields"])
+
+    def test_provenance_fields(self):
+        before = dt.datetime.now(dt.timezone.utc).replace(microsecond=0)
+        aurn = convert({"Provenance Fields": True})
+        after = dt.datetime.now(dt.timezone.utc)
+        for container in aurn.measurement_jsons[2019]["ABD"]:
+            fields = container["fields"]
+            # An int so it's written as an integer field, bool is an int
+            # subclass so is ruled out too
+            self.assertIs(type(fields["source_year"]), int)
+            self.assertEqual(fields["source_year"], 2019)
+            self.assertIsInstance(fields["scraped_at"], str)
+            scraped_at = dt.datetime.fromisoformat(fields["scraped_at"])
+            self.assertEqual(scraped_at.utcoffset(), dt.timedelta(0))
+            self.assertTrue(before <= scraped_at <= after)
+            # Neither is counted as a measurement
+            self.assertNotIn("source_year", container["tags"])
+        self.assertEqual(
+                aurn.count_valid_measurements("ABD", 2019),
+                count_measurements(aurn.measurement_jsons[2019]["ABD"])
+                )
+
+
+class TestYearRange(unittest.TestCase):
+    """ Rejecting years outside Min Year to Max Year
+    """
+    def setUp(self):
+        self.session = FakeSession(
+                {csv_url("ABD", 2019): "ABD_2019.csv"}
+                )
+        self.aurn = AURNAPI(
+                fake_config({"Min Year": 2000, "Max Year": 2020}),
+                session=self.session
+                )
+
+    def test_validate_year(self):
+        self.assertEqual(validate_year("2000", 2000, 2020), 2000)
+        self.assertEqual(validate_year(2020, 2000, 2020), 2020)
+        for year in [1999, 2021, 219, 20199]:
+            with self.subTest(year=year):
+                with self.assertRaises(ValueError):
+                    validate_year(year, 2000, 2020)
+
+    def test_clamped_at_both_ends(self):
+        self.assertEqual(
+                self.aurn.measurement_csv_url("ABD", 2000),
+                csv_url("ABD", 2000)
+                )
+        self.assertEqual(
+                self.aurn.measurement_csv_url("ABD", 2020),
+                csv_url("ABD", 2020)
+                )
+        for year in [1999, 2021]:
+            with self.subTest(year=year):
+                with self.assertRaises(ValueError):
+                    self.aurn.measurement_csv_url("ABD", year)
+                with self.assertRaises(ValueError):
+                    self.aurn.get_csv_measurements("ABD", year)
+        # Years outside the range are rejected before a request is sent
+        self.assertEqual(self.session.requests, list())
+
+
+class TestZoneTag(unittest.TestCase):
+    """ Tagging sites with their agglomeration zone
+    """
+    def zones(self, overrides=None):
+        """ Tags of each site in metadata, keyed by site name
+        """
+        aurn, session = metadata_api(overrides=overrides)
+        aurn.get_metadata(2019, 2019)
+        # Only pages with a fixture were requested
+        self.assertTrue(
+                all(url in session.routes for url in session.requests)
+                )
+        return {
+                station["tags"]["Site Name"]: station["tags"]
+                for station in aurn.metadata
+                }
+
+    def test_zone_tag(self):
+        tags = self.zones()
+        self.assertEqual(tags["Aberdeen"]["Zone"], "North East Scotland")
+        self.assertEqual(
+                tags["Future End"]["Zone"], "Greater London Urban Area"
+                )
+
+    def test_renamed(self):
+        tags = self.zones({"Zone Tag": "zone"})
+        self.assertEqual(tags["Aberdeen"]["zone"], "North East Scotland")
+        self.assertNotIn("Zone", tags["Aberdeen"])
+
+    def test_disabled(self):
+        tags = self.zones({"Zone Tag": ""})
+        for site_tags in tags.values():
+            self.assertNotIn("Zone", site_tags)
+            self.assertNotIn("", site_tags)
+
+    def test_no_zone(self):
+        aurn, session = metadata_api(metadata_csv="metadata_no_zone.csv")
+        aurn.get_metadata(2019, 2019)
+        self.assertEqual(site_names(aurn), ["Aberdeen"])
+        self.assertNotIn("Zone", aurn.metadata[0]["tags"])
+
+
+class TestBoundaryDuplicates(unittest.TestCase):
+    """ Removing measurements exported from the previous year's csv
+    """
+    def test_boundary_duplicates(self):
+        # The 2019 csv ends at midnight on 1st January 2020, which the
+        # 2020 csv starts with
+        aurn = measurement_api(years=(2019, 2020))
+        for year in [2019, 2020]:
+            aurn.csv_to_json_list(STATION, "ABD", year)
+            aurn.remove_boundary_duplicates("ABD", year)
+        self.assertIn(dt.datetime(2020, 1, 1), containers_by_time(aurn))
+        containers_2020 = containers_by_time(aurn, 2020)
+        self.assertNotIn(dt.datetime(2020, 1, 1), containers_2020)
+        self.assertIn(dt.datetime(2020, 1, 1, 1), containers_2020)
+        self.assertEqual(aurn.boundary_duplicates, 5)
+
+    def test_only_duplicates_removed(self):
+        # Only the duplicated measurement is removed from the boundary
+        # hour and containers that never had a measurement are kept
+        aurn = AURNAPI(fake_config(), session=FakeSession())
+        boundary = dt.datetime(2020, 1, 1)
+        aurn.measurement_jsons[2019]["ABD"] = [
+                {
+                    "time": boundary,
+                    "measurement": "Automatic Urban Rural Network",
+                    "tags": dict(STATION["tags"]),
+                    "fields": {"Latitude": 57.15736, "Ozone": 30.0}
+                }
+                ]
+        aurn.measurement_jsons[2020]["ABD"] = [
+                {
+                    "time": boundary,
+                    "measurement": "Automatic Urban Rural Network",
+                    "tags": dict(STATION["tags"]),
+                    "fields": {"Latitude": 57.15736, "Ozone": 30.0,
+                               "Nitrogen dioxide": 9.0}
+                },
+                {
+                    "time": dt.datetime(2020, 1, 1, 1),
+                    "measurement": "Automatic Urban Rural Network",
+                    "tags": dict(STATION["tags"]),
+                    "fields": {"Latitude": 57.15736}
+                }
+                ]
+        for year in [2019, 2020]:
+            aurn.remove_boundary_duplicates("ABD", year)
+        self.assertEqual(aurn.boundary_duplicates, 1)
+        containers_2020 = containers_by_time(aurn, 2020)
+        self.assertEqual(
+                containers_2020[boundary]["fields"],
+                {"Latitude": 57.15736, "Nitrogen dioxide": 9.0}
+                )
+        self.assertEqual(
+                containers_2020[dt.datetime(2020, 1, 1, 1)]["fields"],
+                {"Latitude": 57.15736}
+                )
+
+
+class TestSitesByPollutantCount(unittest.TestCase):
+    """ Ranking sites by the number of pollutants they measure
+    """
+    def setUp(self):
+        self.aurn = AURNAPI(fake_config(), session=FakeSession())
+
+    def station(self, site_name, download_code, row):
+        """ Adds a site to metadata with pollutants from a metadata row
+        """
+        self.aurn.metadata.append(
+                {
+                    "tags": {
+                        "Site Name": site_name,
+                        "Download Code": download_code
+                    },
+                    "fields": dict(),
+                    "pollutants": self.aurn.pollutant_list(row)
+                }
+                )
+
+    def test_pollutant_list(self):
+        self.assertEqual(
+                self.aurn.pollutant_list(
+                    {"Pollutants": "Ozone; Nitrogen dioxide,, PM10 "}
+                    ),
+                ["Ozone", "Nitrogen dioxide", "PM10"]
+                )
+        # Blank columns are skipped for the next in POLLUTANT_LIST_COLUMNS
+        self.assertEqual(
+                self.aurn.pollutant_list(
+                    {"Pollutants": "", "Parameters": "Ozone"}
+                    ),
+                ["Ozone"]
+                )
+        self.assertEqual(self.aurn.pollutant_list({"Pollutants": "nan"}),
+                         list())
+
+    def test_descending_order(self):
+        self.station("Bristol", "BRS8", {"Pollutants": "Ozone"})
+        self.station("Aberdeen", "ABD",
+                     {"Pollutants": "Ozone,Nitrogen dioxide,PM10"})
+        self.station("Camden", "CA1", dict())
+        self.station("Leeds", "LEED",
+                     {"Pollutants Measured": "Ozone;Nitrogen dioxide"})
+        self.station("Belfast", "BEL2",
+                     {"Parameters": "Ozone,Nitrogen dioxide,PM2.5"})
+        # Ties are ordered by site name
+        self.assertEqual(
+                self.aurn.sites_by_pollutant_count(),
+                [
+                    ("Aberdeen", "ABD", 3),
+                    ("Belfast", "BEL2", 3),
+                    ("Leeds", "LEED", 2),
+                    ("Bristol", "BRS8", 1),
+                    ("Camden", "CA1", 0)
+                ]
+                )
+
+
+class TestFutureStartDate(unittest.TestCase):
+    """ Dropping planned sites whose start date is in the future
+    """
+    def dropped(self, overrides=None):
+        """ Sites in metadata and reasons sites were dropped, keyed by name
+        """
+        aurn, session = metadata_api(
+                overrides=overrides,
+                metadata_csv="metadata_future_start.csv"
+                )
+        aurn.get_metadata(2019, dt.datetime.now().year)
+        self.assertEqual(site_names(aurn), ["Aberdeen"])
+        return {
+                row["Site Name"]: reason for reason, row in aurn.dropped_sites
+                }
+
+    def test_not_yet_active(self):
+        self.assertEqual(self.dropped(), {"Planned Site": "Not yet active"})
+
+    def test_kept_until_date_range(self):
+        # Without the check the planned site is still dropped, as it isn't
+        # active in the date range
+        self.assertEqual(
+                self.dropped({"Drop Not Yet Active Sites": False}),
+                {"Planned Site": "Not active in date range"}
+                )
+
+
+class TestRateLimiting(unittest.TestCase):
+    """ Backing off when DEFRA rate limits or challenges requests
+    """
+    url = f"{DOMAIN}/page"
+    html_headers = {"Content-Type": "text/html"}
+
+    def test_retry_after(self):
+        rate_limited = FakeResponse(b"", 429, {"Retry-After": "0"})
+        session = FakeSession(
+                {self.url: [rate_limited, FakeResponse(b"page")]}
+                )
+        aurn = AURNAPI(fake_config(), session=session)
+        self.assertEqual(aurn.get(self.url).content, b"page")
+        self.assertEqual(aurn.rate_limited_count, 1)
+        self.assertEqual(len(session.requests), 2)
+        # Closed before waiting to retry, so the connection is reused
+        self.assertTrue(rate_limited.closed)
+
+    def test_challenge_page(self):
+        challenge = FakeResponse(
+                b'<div class="g-recaptcha" data-sitekey="key"></div>', 200,
+                self.html_headers
+                )
+        page = FakeResponse(b"<p>page</p>", 200, self.html_headers)
+        session = FakeSession({self.url: [challenge, page]})
+        aurn = AURNAPI(fake_config(), session=session)
+        self.assertEqual(aurn.get(self.url).content, b"<p>page</p>")
+        self.assertEqual(aurn.rate_limited_count, 1)
+        self.assertTrue(challenge.closed)
+        self.assertFalse(page.closed)
+
+    def test_page_mentioning_captcha(self):
+        # Only a captcha element marks a challenge page, not the word
+        session = FakeSession(
+                {
+                    self.url: FakeResponse(
+                        b"<p>No captcha is needed to download data</p>", 200,
+                        self.html_headers
+                        )
+                }
+                )
+        aurn = AURNAPI(fake_config(), session=session)
+        aurn.get(self.url)
+        self.assertEqual(aurn.rate_limited_count, 0)
+        self.assertEqual(len(session.requests), 1)
+
+    def test_still_rate_limited(self):
+        rate_limited = FakeResponse(b"", 429, {"Retry-After": "0"})
+        session = FakeSession({self.url: rate_limited})
+        aurn = AURNAPI(fake_config({"Rate Limit Retries": 2}),
+                       session=session)
+        with self.assertRaises(AURNRateLimitedError):
+            aurn.get(self.url)
+        self.assertEqual(len(session.requests), 3)
+        self.assertTrue(rate_limited.closed)
+
+    def test_retry_after_seconds(self):
+        self.assertEqual(retry_after_seconds("120", 5), 120)
+        self.assertEqual(retry_after_seconds(None, 5), 5)
+        self.assertEqual(retry_after_seconds("soon", 5), 5)
+        self.assertEqual(
+                retry_after_seconds("Wed, 21 Oct 2015 07:28:00 GMT", 5), 0
+                )
+        # -0000 gives a date without a timezone, which is still UTC
+        self.assertGreater(
+                retry_after_seconds("Wed, 21 Oct 2099 07:28:00 -0000", 5), 0
+                )
+
+
+class TestMissingCsv(unittest.TestCase):
+    """ Skipping years a site has no measurement csv for
+    """
+    def test_missing_csv(self):
+        aurn = measurement_api(years=())
+        self.assertIsNone(aurn.get_csv_measurements("ABD", 2018))
+        self.assertEqual(aurn.failed_requests, 1)
+        self.assertEqual(aurn.request_errors[-1].status_code, 404)
+        self.assertIsNone(aurn.measurement_csvs[2018]["ABD"])
+
+
+class TestMetadataColumns(unittest.TestCase):
+    """ Keeping only the metadata tags and fields listed in config
+    """
+    def setUp(self):
+        self.aurn = AURNAPI(fake_config(), session=FakeSession())
+        self.aurn.metadata.append(
+                {
+                    "tags": {
+                        "UK-AIR ID": "UKA00399",
+                        "EU Site ID": "GB0999A",
+                        "Site Name": "Aberdeen",
+                        "Environment Type": "Urban Background",
+                        "Download Code": "ABD",
+                        "Zone": "North East Scotland"
+                    },
+                    "fields": {
+                        "Latitude": 57.15736,
+                        "Longitude": -2.094278,
+                        "Altitude": 20.0
+                    }
+                }
+                )
+
+    def test_selected_in_order(self):
+        self.aurn.select_metadata_columns(["Altitude", "Zone", "Latitude"])
+        station = self.aurn.metadata[0]
+        self.assertEqual(
+                list(station["tags"].items()),
+                [
+                    ("UK-AIR ID", "UKA00399"),
+                    ("Download Code", "ABD"),
+                    ("Site Name", "Aberdeen"),
+                    ("Zone", "North East Scotland")
+                ]
+                )
+        self.assertEqual(
+                list(station["fields"].items()),
+                [("Altitude", 20.0), ("Latitude", 57.15736)]
+                )
+
+    def test_site_keys_kept(self):
+        # UK-AIR ID identifies the site so is kept even if not listed
+        self.aurn.select_metadata_columns(["Latitude", "Site Name"])
+        self.assertEqual(
+                list(self.aurn.metadata[0]["tags"]),
+                ["UK-AIR ID", "Download Code", "Site Name"]
+                )
+
+    def test_unknown_column(self):
+        with self.assertRaisesRegex(
+                ValueError, "unknown columns: Colour, Height"
+                ):
+            self.aurn.select_metadata_columns(
+                    ["Latitude", "Colour", "Height"]
+                    )
+
+    def test_config(self):
+        aurn, session = metadata_api(
+                overrides={"Metadata Columns": ["Longitude", "Region"]}
+                )
+        aurn.get_metadata(2019, 2019)
+        aberdeen = aurn.metadata[0]
+        self.assertEqual(
+                list(aberdeen["tags"]),
+                ["UK-AIR ID", "Download Code", "Site Name", "Region"]
+                )
+        self.assertEqual(list(aberdeen["fields"]), ["Longitude"])
+
+
+class TestGzippedPages(unittest.TestCase):
+    """ Decompressing pages gzipped without a Content-Encoding header
+    """
+    def test_response_content(self):
+        page = read_fixture("find_sites.html")
+        self.assertEqual(
+                response_content(FakeResponse(gzip.compress(page))), page
+                )
+        self.assertEqual(response_content(FakeResponse(page)), page)
+        # Not actually gzipped, returned as it is
+        self.assertEqual(
+                response_content(FakeResponse(b"\x1f\x8bnot gzip")),
+                b"\x1f\x8bnot gzip"
+                )
+
+    def test_gzipped_metadata_page(self):
+        # requests wouldn't have decompressed it without the header
+        aurn, session = metadata_api(
+                FakeResponse(
+                    gzip.compress(read_fixture("find_sites.html")),
+                    headers={"Content-Type": "text/html"}
+                    )
+                )
+        self.assertEqual(len(aurn.get_metadata_table()), 5)
+
+
+class TestUnitPlacement(unittest.TestCase):
+    """ Exporting units as tags, fields or not at all
+    """
+    def first_hour(self, unit_placement):
+        """ Container for 01:00 with unit_placement
+        """
+        aurn = convert({"Unit Placement": unit_placement})
+        return containers_by_time(aurn)[dt.datetime(2019, 1, 1, 1)]
+
+    def test_tag(self):
+        container = self.first_hour("Tag")
+        self.assertEqual(container["tags"]["Nitrogen dioxide unit"], "ugm-3")
+        self.assertNotIn("Nitrogen dioxide unit", container["fields"])
+
+    def test_field(self):
+        container = self.first_hour("Field")
+        self.assertEqual(
+                container["fields"]["Nitrogen dioxide unit"], "ugm-3"
+                )
+        self.assertNotIn("Nitrogen dioxide unit", container["tags"])
+        self.assertEqual(container["tags"]["Nitrogen dioxide status"], "V")
+
+    def test_omit(self):
+        container = self.first_hour("Omit")
+        self.assertNotIn("Nitrogen dioxide unit", container["tags"])
+        self.assertNotIn("Nitrogen dioxide unit", container["fields"])
+        self.assertEqual(container["tags"]["Nitrogen dioxide status"], "V")
+
+    def test_invalid(self):
+        with self.assertRaisesRegex(ValueError, "not a valid unit placement"):
+            convert({"Unit Placement": "Column"})
+
+
+class TestStatuses(unittest.TestCase):
+    """ Reading measurements and keeping only allowed statuses
+    """
+    def test_unreadable_measurement(self):
+        # 3.5x isn't a number or a known sentinel, it is skipped without
+        # losing the rest of the row
+        aurn = convert()
+        fields = containers_by_time(aurn)[dt.datetime(2019, 1, 1, 7)][
+                "fields"]
+        self.assertNotIn("Nitrogen dioxide", fields)
+        self.assertEqual(fields["Nitric oxide"], 2.0)
+        self.assertEqual(fields["Ozone"], 25.0)
+        self.assertEqual(
+                aurn.count_valid_measurements("ABD", 2019),
+                count_measurements(aurn.measurement_jsons[2019]["ABD"])
+                )
+
+    def test_status_tag(self):
+        containers = containers_by_time(convert())
+        self.assertEqual(
+                containers[dt.datetime(2019, 1, 1, 1)]["tags"][
+                    "Nitrogen dioxide status"],
+                "V"
+                )
+        self.assertEqual(
+                containers[dt.datetime(2019, 1, 1, 2)]["tags"][
+                    "Nitrogen dioxide status"],
+                "P"
+                )
+
+    def test_allowed_statuses(self):
+        aurn = convert({"Allowed Statuses": ["V"]})
+        containers = containers_by_time(aurn)
+        # Provisional at 02:00, no status at 05:00
+        for hour in [2, 5]:
+            self.assertNotIn(
+                    "Nitrogen dioxide",
+                    containers[dt.datetime(2019, 1, 1, hour)]["fields"]
+                    )
+        self.assertEqual(
+                containers[dt.datetime(2019, 1, 1, 3)]["fields"][
+                    "Nitrogen dioxide"],
+                12.0
+                )
+        self.assertEqual(aurn.dropped_statuses, 2)
+        self.assertEqual(
+                aurn.count_valid_measurements("ABD", 2019),
+                count_measurements(aurn.measurement_jsons[2019]["ABD"])
+                )
+
+    def test_blank_status(self):
+        # A blank status is read by pandas as NaN, it shouldn't become a
+        # "nan" tag
+        containers = containers_by_time(convert())
+        container = containers[dt.datetime(2019, 1, 1, 5)]
+        self.assertEqual(container["fields"]["Nitrogen dioxide"], 14.0)
+        self.assertNotIn("Nitrogen dioxide status", container["tags"])
+        # Nor should a blank unit
+        self.assertNotIn(
+                "PM10 particulate matter unit",
+                containers[dt.datetime(2019, 1, 1, 2)]["tags"]
+                )
+
+
+@unittest.skipUnless(hasattr(time, "tzset"), "time.tzset is Unix only")
+class TestHostTimezone(unittest.TestCase):
+    """ Reading measurement times as GMT whatever the host timezone is
+    """
+    # 13:00 on 1st July 2021 in seconds since epoch
+    one_pm = 1625144400
+
+    def setUp(self):
+        # New York is behind GMT and London is on BST in July, so either
+        # being used by mistake moves the time
+        self.addCleanup(self.restore_timezone, os.environ.get("TZ"))
+        os.environ["TZ"] = "America/New_York"
+        time.tzset()
+
+    @staticmethod
+    def restore_timezone(timezone):
+        if timezone is None:
+            os.environ.pop("TZ", None)
+        else:
+            os.environ["TZ"] = timezone
+        time.tzset()
+
+    def test_afternoon(self):
+        container = containers_by_time(convert(year=2021), 2021)[
+                dt.datetime(2021, 7, 1, 13)
+                ]
+        self.assertEqual(
+                timestamp_nanoseconds(container["time"]),
+                self.one_pm * 10**9
+                )
+        datapoints = OpenTSDBWriter(
+                {
+                    "OpenTSDB IP": "localhost",
+                    "OpenTSDB Port": "4242",
+                    "OpenTSDB Metric Prefix": "aurn."
+                }
+                ).container_list_to_datapoints([container])
+        self.assertEqual(
+                {datapoint["timestamp"] for datapoint in datapoints},
+                {self.one_pm}
+                )
+
+    def test_midnight(self):
+        # 24:00 is midnight at the start of the next day
+        containers = containers_by_time(convert(year=2021), 2021)
+        self.assertEqual(
+                timestamp_nanoseconds(
+                    containers[dt.datetime(2021, 7, 2)]["time"]
+                    ),
+                (self.one_pm + 11 * 3600) * 10**9
+                )
+
+
+class TestReversedDates(unittest.TestCase):
+    """ Rejecting date ranges that end before they start
+    """
+    def test_reversed_years(self):
+        aurn, session = metadata_api()
+        with self.assertRaisesRegex(
+                ValueError, "End year 2019 is before start year 2020"
+                ):
+            aurn.get_metadata(2020, 2019)
+        # Rejected before the metadata is requested
+        self.assertEqual(session.requests, list())
+
+
+class TestDroppedSites(unittest.TestCase):
+    """ Skipping sites that can't be exported without stopping the scrape
+    """
+    def test_dropped_sites(self):
+        # No ID has no UK-AIR ID, the sites after it are still read
+        aurn, session = metadata_api()
+        self.assertEqual(aurn.get_metadata(2019, 2019), 1)
+        self.assertEqual(site_names(aurn), ["Aberdeen", "Future End"])
+        dropped = {
+                row["Site Name"]: reason for reason, row in aurn.dropped_sites
+                }
+        self.assertEqual(
+                dropped,
+                {
+                    "No ID": "No UK-AIR ID",
+                    "Closed Site": "Not active in date range",
+                    "Not AURN": "Not an AURN site"
+                }
+                )
+
+
+if __name__ == "__main__":
+    unittest.main()
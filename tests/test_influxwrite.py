@@ -0,0 +1,174 @@
+""" Tests for modules/influxwrite.py
+
+InfluxDBClient doesn't connect until a request is sent, the write api is
+replaced by FakeWriteApi so nothing is sent
+
+"""
+
+import datetime as dt
+import unittest
+
+from influxdb_client.rest import ApiException
+
+from modules.influxwrite import InfluxWriter
+
+
+class FakeWriteApi:
+    """ Stands in for the influxdb_client write api, recording each write
+
+    Attributes:
+        batches (list): Containers sent in each write
+
+        destinations (list): Bucket and organisation of each write
+
+        errors (list): Exceptions to raise for the next writes, in order
+    """
+    def __init__(self, errors=None):
+        self.batches = list()
+        self.destinations = list()
+        self.errors = list(errors or list())
+
+    def write(self, bucket, org, record):
+        if len(self.errors) > 0:
+            raise self.errors.pop(0)
+        self.batches.append(list(record))
+        self.destinations.append((bucket, org))
+
+
+def containers(count):
+    """ count containers an hour apart
+    """
+    return [
+            {
+                "time": dt.datetime(2019, 1, 1) + dt.timedelta(hours=hour),
+                "measurement": "Automatic Urban Rural Network",
+                "tags": {"Download Code": "ABD"},
+                "fields": {"Nitrogen dioxide": float(hour)}
+            }
+            for hour in range(1, count + 1)
+            ]
+
+
+def influx_writer(overrides=None, errors=None):
+    """ InfluxWriter writing to a FakeWriteApi
+    """
+    config = {
+            "Influx URL": "http://localhost:8086",
+            "Influx Token": "token",
+            "Influx Bucket": "aurn",
+            "Influx Organisation": "org",
+            "Influx Retry Backoff": 0
+            }
+    config.update(overrides or dict())
+    writer = InfluxWriter(config)
+    writer.write_client = FakeWriteApi(errors)
+    return writer
+
+
+class TestWriteRetries(unittest.TestCase):
+    """ Retrying writes that fail for transient reasons
+    """
+    def test_server_error_retried(self):
+        writer = influx_writer(errors=[ApiException(status=503)])
+        writer.write_container_list(containers(1))
+        writer.flush()
+        self.assertEqual(len(writer.write_client.batches), 1)
+        self.assertEqual(writer.retry_policy.retry_count, 1)
+
+    def test_dropped_connection_retried(self):
+        writer = influx_writer(errors=[ConnectionError()])
+        writer.write_container_list(containers(1))
+        writer.flush()
+        self.assertEqual(len(writer.write_client.batches), 1)
+
+    def test_client_error_not_retried(self):
+        writer = influx_writer(errors=[ApiException(status=400)])
+        writer.write_container_list(containers(1))
+        with self.assertRaises(ApiException):
+            writer.flush()
+        self.assertEqual(writer.write_client.batches, list())
+
+    def test_out_of_retries(self):
+        writer = influx_writer(
+                {"Influx Write Retries": 1},
+                errors=[ApiException(status=503), ApiException(status=503)]
+                )
+        writer.write_container_list(containers(1))
+        with self.assertRaises(ApiException):
+            writer.flush()
+
+
+class TestBatching(unittest.TestCase):
+    """ Writing containers in batches of Influx Batch Size
+    """
+    def test_batches(self):
+        # Batches can span several lists of containers, the last partial
+        # batch is written by flush
+        writer = influx_writer({"Influx Batch Size": 2})
+        writer.write_container_list(containers(3))
+        writer.write_container_list(containers(2))
+        self.assertEqual(writer.write_count, 2)
+        writer.flush()
+        self.assertEqual(
+                [len(batch) for batch in writer.write_client.batches],
+                [2, 2, 1]
+                )
+        self.assertEqual(writer.write_count, 3)
+
+    def test_unbatched(self):
+        writer = influx_writer({"Influx Batch Size": 0})
+        writer.write_container_list(containers(3))
+        writer.write_container_list(containers(2))
+        writer.flush()
+        self.assertEqual(
+                [len(batch) for batch in writer.write_client.batches],
+                [3, 2]
+                )
+
+    def test_nothing_to_flush(self):
+        writer = influx_writer({"Influx Batch Size": 2})
+        writer.write_container_list(containers(2))
+        writer.flush()
+        self.assertEqual(writer.write_count, 1)
+
+
+class TestInfluxVersion(unittest.TestCase):
+    """ Writing to InfluxDB 2.x or 1.8
+    """
+    def test_version_2(self):
+        writer = influx_writer()
+        writer.write_container_list(containers(1))
+        writer.flush()
+        self.assertEqual(writer.write_client.destinations, [("aurn", "org")])
+
+    def test_version_1(self):
+        # Written to database/retention policy through the v2 api
+        writer = influx_writer(
+                {
+                    "Influx Version": 1,
+                    "Influx Database": "aurn",
+                    "Influx Retention Policy": "autogen",
+                    "Influx Username": "user",
+                    "Influx Password": "password"
+                }
+                )
+        writer.write_container_list(containers(1))
+        writer.flush()
+        self.assertEqual(
+                writer.write_client.destinations, [("aurn/autogen", "-")]
+                )
+
+    def test_default_retention_policy(self):
+        writer = influx_writer(
+                {"Influx Version": 1, "Influx Database": "aurn"}
+                )
+        self.assertEqual(writer.bucket, "aurn")
+        self.assertEqual(writer.organisation, "-")
+
+    def test_unsupported_version(self):
+        with self.assertRaises(ValueError):
+            influx_writer({"Influx Version": 3})
+
+
+if __name__ == "__main__":
+    unittest.main()
@@ -0,0 +1,294 @@
+""" Tests for modules/lineprotocol.py
+
+"""
+
+import datetime as dt
+import gzip
+import io
+import json
+import os
+import tempfile
+import unittest
+
+from modules.lineprotocol import (
+        LineProtocolFileWriter,
+        LineProtocolStreamWriter,
+        timestamp_nanoseconds
+        )
+
+# 2019-01-01 01:00 in nanoseconds since epoch
+FIRST_HOUR = 1546304400000000000
+
+
+def container(time, value=10.0, period_start=False):
+    """ Container with one measurement at time
+    """
+    measurement_container = {
+            "time": time,
+            "measurement": "Automatic Urban Rural Network",
+            "tags": {"Site Name": "Aberdeen", "Download Code": "ABD"},
+            "fields": {"Nitrogen dioxide": value}
+            }
+    if period_start:
+        measurement_container["period_start"] = True
+    return measurement_container
+
+
+def read_lines(path):
+    """ Reads every line of a gzipped file, across all gzip members
+    """
+    with gzip.open(path, "rt", encoding="utf-8") as lp_file:
+        return lp_file.read().splitlines()
+
+
+class TestLineProtocol(unittest.TestCase):
+    """ Converting containers to line protocol
+    """
+    def setUp(self):
+        self.directory = tempfile.TemporaryDirectory()
+        self.writer = LineProtocolFileWriter(
+                {
+                    "Line Protocol File": os.path.join(
+                        self.directory.name, "aurn.lp.gz"
+                        )
+                }
+                )
+
+    def tearDown(self):
+        self.directory.cleanup()
+
+    def test_timestamp_nanoseconds(self):
+        self.assertEqual(
+                timestamp_nanoseconds(dt.datetime(2019, 1, 1, 1)),
+                FIRST_HOUR
+                )
+        self.assertEqual(
+                timestamp_nanoseconds(
+                    dt.datetime(2019, 1, 1, 1, tzinfo=dt.timezone.utc)
+                    ),
+                FIRST_HOUR
+                )
+
+    def test_container_to_line(self):
+        self.assertEqual(
+                self.writer.container_to_line(
+                    container(dt.datetime(2019, 1, 1, 1))
+                    ),
+                "Automatic\\ Urban\\ Rural\\ Network,Download\\ Code=ABD,"
+                "Site\\ Name=Aberdeen Nitrogen\\ dioxide=10.0 "
+                f"{FIRST_HOUR}"
+                )
+
+    def test_escaping(self):
+        # Commas, spaces and equals signs are escaped in tags and field
+        # keys. Measurement names only need commas and spaces escaped
+        measurement_container = {
+                "time": dt.datetime(2019, 1, 1, 1),
+                "measurement": "AURN, London=Sites",
+                "tags": {
+                    "Site Name": "London, Marylebone Road",
+                    "Zone=Area": "Greater London, Urban=Area"
+                },
+                "fields": {
+                    "PM2.5, FIDAS=1": 12.0,
+                    "note": 'C:\\data "raw"'
+                }
+                }
+        self.assertEqual(
+                self.writer.container_to_line(measurement_container),
+                "AURN\\,\\ London=Sites,"
+                "Site\\ Name=London\\,\\ Marylebone\\ Road,"
+                "Zone\\=Area=Greater\\ London\\,\\ Urban\\=Area "
+                "PM2.5\\,\\ FIDAS\\=1=12.0,"
+                'note="C:\\\\data \\"raw\\"" '
+                f"{FIRST_HOUR}"
+                )
+        # JSON points don't need escaping
+        self.assertEqual(
+                self.writer.container_to_point(measurement_container)[
+                    "tags"]["Site Name"],
+                "London, Marylebone Road"
+                )
+
+    def test_field_types(self):
+        # Integers have the i suffix and booleans aren't integers, as
+        # influxdb_client writes them
+        measurement_container = container(dt.datetime(2019, 1, 1, 1))
+        measurement_container["fields"].update(
+                {"source_year": 2019, "provisional": True, "count": False}
+                )
+        line = self.writer.container_to_line(measurement_container)
+        self.assertIn(
+                "Nitrogen\\ dioxide=10.0,source_year=2019i,"
+                "provisional=true,count=false ",
+                line
+                )
+        self.assertEqual(
+                self.writer.container_to_point(measurement_container)[
+                    "fields"
+                    ],
+                {
+                    "Nitrogen dioxide": 10.0,
+                    "source_year": 2019,
+                    "provisional": True,
+                    "count": False
+                }
+                )
+
+    def test_no_fields(self):
+        measurement_container = container(dt.datetime(2019, 1, 1, 1))
+        measurement_container["fields"] = dict()
+        self.assertEqual(
+                self.writer.container_to_line(measurement_container), ""
+                )
+
+
+class TestLineProtocolFileWriter(unittest.TestCase):
+    """ Writing gzipped line protocol files
+    """
+    def setUp(self):
+        self.directory = tempfile.TemporaryDirectory()
+        self.path = os.path.join(self.directory.name, "aurn.lp.gz")
+
+    def tearDown(self):
+        self.directory.cleanup()
+
+    def test_round_trip(self):
+        # Each list of containers is its own gzip member, all of them are
+        # read back
+        writer = LineProtocolFileWriter({"Line Protocol File": self.path})
+        writer.write_container_list([container(dt.datetime(2019, 1, 1, 1))])
+        writer.write_container_list(
+                [container(dt.datetime(2019, 1, 1, 2), 11.0)]
+                )
+        lines = read_lines(self.path)
+        self.assertEqual(len(lines), 2)
+        self.assertTrue(lines[0].endswith(f"=10.0 {FIRST_HOUR}"))
+        self.assertTrue(
+                lines[1].endswith(f"=11.0 {FIRST_HOUR + 3600 * 10**9}")
+                )
+
+    def test_emptied_on_start(self):
+        writer = LineProtocolFileWriter({"Line Protocol File": self.path})
+        writer.write_container_list([container(dt.datetime(2019, 1, 1, 1))])
+        LineProtocolFileWriter({"Line Protocol File": self.path})
+        self.assertEqual(read_lines(self.path), list())
+
+    def test_json(self):
+        writer = LineProtocolFileWriter(
+                {
+                    "Line Protocol File": self.path,
+                    "Line Protocol Body Format": "JSON"
+                }
+                )
+        writer.write_container_list([container(dt.datetime(2019, 1, 1, 1))])
+        writer.write_container_list(
+                [container(dt.datetime(2019, 1, 1, 2), 11.0)]
+                )
+        self.assertEqual(
+                [json.loads(line) for line in read_lines(self.path)],
+                [
+                    [{
+                        "measurement": "Automatic Urban Rural Network",
+                        "tags": {"Download Code": "ABD",
+                                 "Site Name": "Aberdeen"},
+                        "fields": {"Nitrogen dioxide": 10.0},
+                        "timestamp": FIRST_HOUR
+                    }],
+                    [{
+                        "measurement": "Automatic Urban Rural Network",
+                        "tags": {"Download Code": "ABD",
+                                 "Site Name": "Aberdeen"},
+                        "fields": {"Nitrogen dioxide": 11.0},
+                        "timestamp": FIRST_HOUR + 3600 * 10**9
+                    }]
+                ]
+                )
+
+    def test_partitioned(self):
+        # Midnight at the end of 2019 is in the 2019 file, a daily
+        # aggregate for 1st January 2020 is in the 2020 file
+        writer = LineProtocolFileWriter(
+                {"Line Protocol File": self.path, "File Partition": "Year"}
+                )
+        writer.write_container_list(
+                [
+                    container(dt.datetime(2019, 12, 31, 23)),
+                    container(dt.datetime(2020, 1, 1)),
+                    container(dt.datetime(2020, 1, 1, 1))
+                ]
+                )
+        writer.write_container_list(
+                [container(dt.datetime(2020, 1, 1), period_start=True)]
+                )
+        self.assertFalse(os.path.exists(self.path))
+        self.assertEqual(
+                len(read_lines(os.path.join(self.directory.name,
+                                            "aurn_2019.lp.gz"))),
+                2
+                )
+        self.assertEqual(
+                len(read_lines(os.path.join(self.directory.name,
+                                            "aurn_2020.lp.gz"))),
+                2
+                )
+
+    def test_invalid_body_format(self):
+        with self.assertRaises(ValueError):
+            LineProtocolFileWriter(
+                    {
+                        "Line Protocol File": self.path,
+                        "Line Protocol Body Format": "CSV"
+                    }
+                    )
+
+
+class TestLineProtocolStreamWriter(unittest.TestCase):
+    """ Writing line protocol to a stream for dry runs
+    """
+    def test_initialised_without_file(self):
+        # Set up the same way as a file writer, without creating a file
+        directory = tempfile.TemporaryDirectory()
+        self.addCleanup(directory.cleanup)
+        current_directory = os.getcwd()
+        os.chdir(directory.name)
+        self.addCleanup(os.chdir, current_directory)
+        stream = io.StringIO()
+        writer = LineProtocolStreamWriter(stream)
+        self.assertIs(writer.stream, stream)
+        self.assertIsNone(writer.path)
+        self.assertEqual(writer.body_format, "Line Protocol")
+        self.assertEqual(writer.partition, "None")
+        self.assertEqual(writer.written_paths, set())
+        self.assertEqual(writer.line_count, 0)
+        self.assertEqual(os.listdir(directory.name), list())
+
+    def test_write_container_list(self):
+        # The same lines a LineProtocolFileWriter would save
+        stream = io.StringIO()
+        writer = LineProtocolStreamWriter(stream)
+        empty_container = container(dt.datetime(2019, 1, 1, 2))
+        empty_container["fields"] = dict()
+        writer.write_container_list(
+                [
+                    container(dt.datetime(2019, 1, 1, 1)),
+                    empty_container,
+                    container(dt.datetime(2019, 1, 1, 3), 'say "hi"')
+                ]
+                )
+        self.assertEqual(
+                stream.getvalue().splitlines(),
+                [
+                    "Automatic\\ Urban\\ Rural\\ Network,Download\\ Code=ABD,"
+                    "Site\\ Name=Aberdeen Nitrogen\\ dioxide=10.0 "
+                    f"{FIRST_HOUR}",
+                    "Automatic\\ Urban\\ Rural\\ Network,Download\\ Code=ABD,"
+                    'Site\\ Name=Aberdeen Nitrogen\\ dioxide="say \\"hi\\"" '
+                    f"{FIRST_HOUR + 2 * 3600 * 10**9}"
+                ]
+                )
+        self.assertEqual(writer.line_count, 2)
+
+
+if __name__ == "__main__":
+    unittest.main()
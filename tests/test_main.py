@@ -0,0 +1,134 @@
+""" Tests for functions in main.py
+
+"""
+
+import contextlib
+import datetime as dt
+import io
+import unittest
+
+from main import (find_count_mismatch, parse_relative_date,
+                  print_count_mismatches)
+from modules.aurn import AURNAPI
+from tests.fakes import DOMAIN, FakeSession, fake_config
+
+
+class TestRelativeDates(unittest.TestCase):
+    """ Start and end dates relative to the current time
+    """
+    now = dt.datetime(2021, 3, 31, 12, 30)
+
+    def test_now(self):
+        self.assertEqual(parse_relative_date("now", self.now), self.now)
+
+    def test_days(self):
+        self.assertEqual(
+                parse_relative_date("30d", self.now),
+                dt.datetime(2021, 3, 1, 12, 30)
+                )
+
+    def test_weeks(self):
+        self.assertEqual(
+                parse_relative_date("2w", self.now),
+                dt.datetime(2021, 3, 17, 12, 30)
+                )
+
+    def test_months(self):
+        # Day is clamped to the end of shorter months
+        self.assertEqual(
+                parse_relative_date("1m", self.now),
+                dt.datetime(2021, 2, 28, 12, 30)
+                )
+        self.assertEqual(
+                parse_relative_date("15m", self.now),
+                dt.datetime(2019, 12, 31, 12, 30)
+                )
+
+    def test_years(self):
+        self.assertEqual(
+                parse_relative_date("1y", self.now),
+                dt.datetime(2020, 3, 31, 12, 30)
+                )
+        self.assertEqual(
+                parse_relative_date("1y", dt.datetime(2020, 2, 29)),
+                dt.datetime(2019, 2, 28)
+                )
+
+    def test_case_and_whitespace(self):
+        self.assertEqual(parse_relative_date(" NOW ", self.now), self.now)
+        self.assertEqual(
+                parse_relative_date("1D", self.now),
+                dt.datetime(2021, 3, 30, 12, 30)
+                )
+
+    def test_invalid(self):
+        for expression in ["", "30", "1h", "-1d", "d", "1.5d", "yesterday"]:
+            with self.subTest(expression=expression):
+                with self.assertRaises(ValueError):
+                    parse_relative_date(expression, self.now)
+
+
+class TestCountMismatches(unittest.TestCase):
+    """ Catching measurements lost converting a csv to containers
+    """
+    def setUp(self):
+        self.aurn = AURNAPI(
+                fake_config(),
+                session=FakeSession(
+                    {
+                        f"{DOMAIN}/data_files/site_data/ABD_2019.csv":
+                            "ABD_2019.csv"
+                    }
+                    )
+                )
+        self.aurn.get_csv_measurements("ABD", 2019)
+        self.aurn.csv_to_json_list(
+                {
+                    "tags": {"Site Name": "Aberdeen", "Download Code": "ABD"},
+                    "fields": {"Latitude": 57.15736}
+                },
+                "ABD",
+                2019
+                )
+
+    def summary(self, count_mismatches):
+        """ Summary printed for count_mismatches
+        """
+        output = io.StringIO()
+        with contextlib.redirect_stdout(output):
+            print_count_mismatches(count_mismatches)
+        return output.getvalue()
+
+    def test_counts_agree(self):
+        self.assertIsNone(
+                find_count_mismatch(self.aurn, "Aberdeen", "ABD", 2019,
+                                    None, None)
+                )
+        self.assertEqual(self.summary(list()), "")
+
+    def test_dropped_measurement(self):
+        # Lose a measurement on purpose, as a bug converting the csv would
+        downloaded_count = self.aurn.count_valid_measurements("ABD", 2019)
+        container = next(
+                container for container in
+                self.aurn.measurement_jsons[2019]["ABD"]
+                if "Nitrogen dioxide" in container["fields"]
+                )
+        del container["fields"]["Nitrogen dioxide"]
+        count_mismatch = find_count_mismatch(
+                self.aurn, "Aberdeen", "ABD", 2019, None, None
+                )
+        self.assertEqual(
+                count_mismatch,
+                ("Aberdeen", 2019, downloaded_count, downloaded_count - 1)
+                )
+        summary = self.summary([count_mismatch])
+        self.assertIn("Measurement count mismatches", summary)
+        self.assertIn(
+                f"Aberdeen (2019): {downloaded_count}/{downloaded_count - 1}",
+                summary
+                )
+
+
+if __name__ == "__main__":
+    unittest.main()
@@ -0,0 +1,137 @@
+""" Tests for modules/opentsdbwrite.py
+
+requests.post is replaced with a mock so nothing is sent to OpenTSDB
+
+"""
+
+import datetime as dt
+import unittest
+from unittest import mock
+
+import requests as req
+
+from modules.opentsdbwrite import OpenTSDBWriter
+
+
+def containers(count):
+    """ count containers an hour apart, each with one pollutant
+    """
+    return [
+            {
+                "time": dt.datetime(2019, 1, 1) + dt.timedelta(hours=hour),
+                "measurement": "Automatic Urban Rural Network",
+                "tags": {
+                    "Site Name": "London Marylebone Road",
+                    "Download Code": "MY1",
+                    "Nitrogen dioxide status": "V"
+                },
+                "fields": {"Nitrogen dioxide": float(hour)}
+            }
+            for hour in range(1, count + 1)
+            ]
+
+
+def opentsdb_writer(overrides=None):
+    """ OpenTSDBWriter without waits between retries
+    """
+    config = {
+            "OpenTSDB IP": "localhost",
+            "OpenTSDB Port": "4242",
+            "OpenTSDB Metric Prefix": "aurn.",
+            "OpenTSDB Retry Backoff": 0
+            }
+    config.update(overrides or dict())
+    return OpenTSDBWriter(config)
+
+
+def response(status_code=200):
+    """ Mock response to a put request
+    """
+    put_response = mock.Mock(status_code=status_code)
+    if status_code >= 400:
+        put_response.raise_for_status.side_effect = req.HTTPError(
+                response=put_response
+                )
+    return put_response
+
+
+def posted_batches(post):
+    """ Datapoints sent in each call to the mocked requests.post
+    """
+    return [call.kwargs["json"] for call in post.call_args_list]
+
+
+class TestOpenTSDBWriter(unittest.TestCase):
+    """ Converting containers to datapoints and writing them in batches
+    """
+    def test_datapoints(self):
+        datapoints = opentsdb_writer().container_list_to_datapoints(
+                containers(1)
+                )
+        self.assertEqual(
+                datapoints,
+                [{
+                    "metric": "aurn.Nitrogen_dioxide",
+                    "timestamp": 1546304400,
+                    "value": 1.0,
+                    "tags": {
+                        "Site_Name": "London_Marylebone_Road",
+                        "Download_Code": "MY1",
+                        "status": "V"
+                    }
+                }]
+                )
+
+    @mock.patch("modules.opentsdbwrite.req.post")
+    def test_batches(self, post):
+        post.return_value = response()
+        writer = opentsdb_writer({"OpenTSDB Batch Size": 2})
+        writer.write_container_list(containers(5))
+        self.assertEqual(
+                [len(batch) for batch in posted_batches(post)], [2, 2, 1]
+                )
+        self.assertEqual(writer.write_count, 3)
+
+    @mock.patch("modules.opentsdbwrite.req.post")
+    def test_unbatched(self, post):
+        post.return_value = response()
+        writer = opentsdb_writer({"OpenTSDB Batch Size": 0})
+        writer.write_container_list(containers(5))
+        self.assertEqual([len(batch) for batch in posted_batches(post)], [5])
+
+    @mock.patch("modules.opentsdbwrite.req.post")
+    def test_only_failed_batch_retried(self, post):
+        post.side_effect = [
+                response(),
+                req.ConnectionError(),
+                response(503),
+                response(),
+                response()
+                ]
+        writer = opentsdb_writer({"OpenTSDB Batch Size": 2})
+        writer.write_container_list(containers(5))
+        timestamps = [
+                [datapoint["timestamp"] for datapoint in batch]
+                for batch in posted_batches(post)
+                ]
+        self.assertEqual(timestamps[1], timestamps[2])
+        self.assertEqual(timestamps[2], timestamps[3])
+        self.assertEqual(len(timestamps), 5)
+        self.assertEqual(writer.write_count, 3)
+
+    @mock.patch("modules.opentsdbwrite.req.post")
+    def test_client_error_not_retried(self, post):
+        post.return_value = response(400)
+        writer = opentsdb_writer()
+        with self.assertRaises(req.HTTPError):
+            writer.write_container_list(containers(1))
+        self.assertEqual(post.call_count, 1)
+
+    @mock.patch("modules.opentsdbwrite.req.post")
+    def test_nothing_to_write(self, post):
+        opentsdb_writer().write_container_list(list())
+        post.assert_not_called()
+
+
+if __name__ == "__main__":
+    unittest.main()
@@ -0,0 +1,153 @@
+""" Tests for modules/parquetwrite.py
+
+"""
+
+import datetime as dt
+import os
+import tempfile
+import unittest
+
+import pyarrow as pa
+import pyarrow.parquet as pq
+
+from modules.parquetwrite import SCHEMA, ParquetWriter
+
+
+def container(time, unit_placement="Tag"):
+    """ Container with two measurements and location fields at time
+    """
+    measurement_container = {
+            "time": time,
+            "measurement": "Automatic Urban Rural Network",
+            "tags": {
+                "Site Name": "Aberdeen",
+                "Download Code": "ABD",
+                "Nitrogen dioxide status": "V"
+            },
+            "fields": {
+                "Latitude": 57.15736,
+                "Nitrogen dioxide": 10.0,
+                "Ozone": 20.0,
+                "local_time": "01:00"
+            }
+            }
+    if unit_placement == "Tag":
+        measurement_container["tags"]["Nitrogen dioxide unit"] = "ugm-3"
+    else:
+        measurement_container["fields"]["Nitrogen dioxide unit"] = "ugm-3"
+    return measurement_container
+
+
+class TestParquetWriter(unittest.TestCase):
+    """ Writing measurements to Parquet files
+    """
+    def setUp(self):
+        # Cleanups run last first, so writers are closed before the
+        # directory is removed
+        self.directory = tempfile.TemporaryDirectory()
+        self.addCleanup(self.directory.cleanup)
+        self.path = os.path.join(self.directory.name, "aurn.parquet")
+
+    def writer(self, overrides=None):
+        config = {"Parquet File": self.path}
+        config.update(overrides or dict())
+        writer = ParquetWriter(config)
+        self.addCleanup(writer.close)
+        return writer
+
+    def test_schema(self):
+        self.assertEqual(
+                SCHEMA.names,
+                ["timestamp", "measurement", "site_code", "pollutant",
+                 "value", "unit", "status"]
+                )
+        self.assertEqual(
+                SCHEMA.field("timestamp").type,
+                pa.timestamp("us", tz="UTC")
+                )
+        self.assertEqual(SCHEMA.field("value").type, pa.float64())
+        for name in ["measurement", "site_code", "pollutant", "unit",
+                     "status"]:
+            with self.subTest(name=name):
+                self.assertEqual(SCHEMA.field(name).type, pa.string())
+
+    def test_container_list_to_table(self):
+        # Location and string fields aren't measurements
+        table = self.writer().container_list_to_table(
+                [container(dt.datetime(2019, 1, 1, 1))]
+                )
+        self.assertEqual(table.schema, SCHEMA)
+        self.assertEqual(
+                table.to_pylist(),
+                [
+                    {
+                        "timestamp": dt.datetime(2019, 1, 1, 1,
+                                                 tzinfo=dt.timezone.utc),
+                        "measurement": "Automatic Urban Rural Network",
+                        "site_code": "ABD",
+                        "pollutant": "Nitrogen dioxide",
+                        "value": 10.0,
+                        "unit": "ugm-3",
+                        "status": "V"
+                    },
+                    {
+                        "timestamp": dt.datetime(2019, 1, 1, 1,
+                                                 tzinfo=dt.timezone.utc),
+                        "measurement": "Automatic Urban Rural Network",
+                        "site_code": "ABD",
+                        "pollutant": "Ozone",
+                        "value": 20.0,
+                        "unit": None,
+                        "status": None
+                    }
+                ]
+                )
+
+    def test_unit_field(self):
+        table = self.writer().container_list_to_table(
+                [container(dt.datetime(2019, 1, 1, 1), "Field")]
+                )
+        self.assertEqual(table.column("unit").to_pylist(), ["ugm-3", None])
+
+    def test_round_trip(self):
+        writer = self.writer()
+        writer.write_container_list([container(dt.datetime(2019, 1, 1, 1))])
+        writer.write_container_list([container(dt.datetime(2019, 1, 1, 2))])
+        writer.close()
+        self.assertEqual(writer.row_count, 4)
+        table = pq.read_table(self.path)
+        self.assertEqual(table.schema, SCHEMA)
+        self.assertEqual(table.num_rows, 4)
+
+    def test_partitioned(self):
+        # Midnight at the end of 2019 is in the 2019 file
+        writer = self.writer({"File Partition": "Year"})
+        writer.write_container_list(
+                [
+                    container(dt.datetime(2019, 12, 31, 23)),
+                    container(dt.datetime(2020, 1, 1)),
+                    container(dt.datetime(2020, 1, 1, 1))
+                ]
+                )
+        writer.close()
+        self.assertFalse(os.path.exists(self.path))
+        self.assertEqual(
+                pq.read_table(os.path.join(self.directory.name,
+                                           "aurn_2019.parquet")).num_rows,
+                4
+                )
+        self.assertEqual(
+                pq.read_table(os.path.join(self.directory.name,
+                                           "aurn_2020.parquet")).num_rows,
+                2
+                )
+
+    def test_invalid_partition(self):
+        with self.assertRaises(ValueError):
+            ParquetWriter(
+                    {"Parquet File": self.path, "File Partition": "Day"}
+                    )
+
+
+if __name__ == "__main__":
+    unittest.main()
@@ -0,0 +1,103 @@
+""" Tests for modules/partition.py
+
+"""
+
+import datetime as dt
+import os
+import unittest
+
+from modules.partition import (
+        partition_container_list,
+        partition_name,
+        partitioned_path,
+        validate_partition
+        )
+
+
+def container(time, period_start=False):
+    """ Container with one measurement at time
+    """
+    measurement_container = {
+            "time": time,
+            "measurement": "Automatic Urban Rural Network",
+            "tags": {"Download Code": "ABD"},
+            "fields": {"Nitrogen dioxide": 10.0}
+            }
+    if period_start:
+        measurement_container["period_start"] = True
+    return measurement_container
+
+
+class TestPartition(unittest.TestCase):
+    """ Splitting containers by the year or month they measure
+    """
+    def test_partition_name(self):
+        self.assertEqual(
+                partition_name(dt.datetime(2019, 3, 5, 1), "Year"), "2019"
+                )
+        self.assertEqual(
+                partition_name(dt.datetime(2019, 3, 5, 1), "Month"),
+                "2019-03"
+                )
+
+    def test_midnight(self):
+        # Midnight at the end of the year is the last hour of that year
+        self.assertEqual(
+                partition_name(dt.datetime(2020, 1, 1), "Year"), "2019"
+                )
+        self.assertEqual(
+                partition_name(dt.datetime(2019, 4, 1), "Month"), "2019-03"
+                )
+
+    def test_period_start(self):
+        # Aggregates are timestamped with the start of the day or month
+        # they cover, so aren't shifted back an hour
+        self.assertEqual(
+                partition_name(dt.datetime(2020, 1, 1), "Year",
+                               period_start=True),
+                "2020"
+                )
+        self.assertEqual(
+                partition_name(dt.datetime(2019, 4, 1), "Month",
+                               period_start=True),
+                "2019-04"
+                )
+
+    def test_partition_container_list(self):
+        containers = [
+                container(dt.datetime(2019, 12, 31, 23)),
+                container(dt.datetime(2020, 1, 1)),
+                container(dt.datetime(2020, 1, 1, 1)),
+                container(dt.datetime(2020, 1, 1), period_start=True)
+                ]
+        partitions = partition_container_list(containers, "Year")
+        self.assertEqual(list(partitions.keys()), ["2019", "2020"])
+        self.assertEqual(partitions["2019"], containers[:2])
+        self.assertEqual(partitions["2020"], containers[2:])
+
+    def test_no_partition(self):
+        containers = [
+                container(dt.datetime(2019, 12, 31, 23)),
+                container(dt.datetime(2020, 1, 1, 1))
+                ]
+        self.assertEqual(
+                partition_container_list(containers, "None"),
+                {None: containers}
+                )
+
+    def test_partitioned_path(self):
+        self.assertEqual(
+                partitioned_path(os.path.join("Output", "aurn.lp.gz"),
+                                 "2019"),
+                os.path.join("Output", "aurn_2019.lp.gz")
+                )
+        self.assertEqual(partitioned_path("aurn.lp.gz", None), "aurn.lp.gz")
+
+    def test_validate_partition(self):
+        self.assertEqual(validate_partition("Month"), "Month")
+        with self.assertRaises(ValueError):
+            validate_partition("Day")
+
+
+if __name__ == "__main__":
+    unittest.main()
@@ -0,0 +1,84 @@
+""" Tests for modules/retry.py
+
+"""
+
+import threading
+import unittest
+
+from modules.retry import RetryPolicy
+
+
+class FlakyFunction:
+    """ Raises ConnectionError the first failures times it is called
+    """
+    def __init__(self, failures):
+        self.failures = failures
+        self.calls = 0
+
+    def __call__(self):
+        self.calls += 1
+        if self.calls <= self.failures:
+            raise ConnectionError("Dropped connection")
+        return "Done"
+
+
+def is_connection_error(error):
+    return isinstance(error, ConnectionError)
+
+
+class TestRetryPolicy(unittest.TestCase):
+    """ Retrying functions that fail for transient reasons
+    """
+    def policy(self, retries=3):
+        return RetryPolicy(retries=retries, backoff=0, jitter=0)
+
+    def test_retried(self):
+        policy = self.policy()
+        function = FlakyFunction(2)
+        self.assertEqual(policy.run(function, is_connection_error), "Done")
+        self.assertEqual(function.calls, 3)
+        self.assertEqual(policy.retry_count, 2)
+
+    def test_out_of_retries(self):
+        policy = self.policy(retries=1)
+        function = FlakyFunction(2)
+        with self.assertRaises(ConnectionError):
+            policy.run(function, is_connection_error)
+        self.assertEqual(function.calls, 2)
+
+    def test_not_retryable(self):
+        policy = self.policy()
+        function = FlakyFunction(1)
+        with self.assertRaises(ConnectionError):
+            policy.run(function, lambda error: False)
+        self.assertEqual(function.calls, 1)
+        self.assertEqual(policy.retry_count, 0)
+
+    def test_delay(self):
+        policy = RetryPolicy(backoff=1, jitter=0)
+        self.assertEqual(
+                [policy.delay(attempt) for attempt in range(0, 3)],
+                [1, 2, 4]
+                )
+
+    def test_shared_between_threads(self):
+        # Metadata and download workers share a policy, every retry should
+        # be counted
+        policy = self.policy()
+        start = threading.Barrier(8)
+
+        def run_flaky():
+            start.wait()
+            for _ in range(0, 200):
+                policy.run(FlakyFunction(1), is_connection_error)
+
+        threads = [threading.Thread(target=run_flaky) for _ in range(0, 8)]
+        for thread in threads:
+            thread.start()
+        for thread in threads:
+            thread.join()
+        self.assertEqual(policy.retry_count, 1600)
+
+
+if __name__ == "__main__":
+    unittest.main()
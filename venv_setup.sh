@@ -5,3 +5,4 @@ pip install influxdb-client
 pip install lxml
 pip install requests
 pip install pandas
+pip install pyarrow